@@ -5,5 +5,5 @@ set -e
 ./github-stats fetch
 ./github-stats generate
 
-cp -r stats/*.svg /domains/ghstats.mydomain.com
+cp -r stats/* /domains/ghstats.mydomain.com
 
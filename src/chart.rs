@@ -1,13 +1,99 @@
+use std::fs::File;
+use std::io::Write;
 use std::path::PathBuf;
+#[cfg(feature = "png")]
+use std::path::Path;
 use std::collections::HashMap;
-use chrono::{Days, NaiveDate, Utc};
+use chrono::{Days, FixedOffset, NaiveDate, NaiveTime, Utc};
+use chrono::format::{Item, StrftimeItems};
 use std::error::Error;
-use plotters::backend::SVGBackend;
-use plotters::prelude::{BLUE, Color, IntoFont, Palette, Palette99, PointSeries, WHITE};
+use plotters::backend::{DrawingBackend, SVGBackend};
+#[cfg(feature = "png")]
+use plotters::backend::BitMapBackend;
+use plotters::coord::Shift;
+use plotters::prelude::{BLACK, BLUE, Color, IntoFont, LineSeries, Palette, Palette99, PointSeries, RGBColor, WHITE};
 use plotters::chart::{ChartBuilder, SeriesLabelPosition};
 use human_format::Formatter;
-use plotters::element::{Circle, EmptyElement, Rectangle, Text};
-use plotters::drawing::IntoDrawingArea;
+use plotters::element::{Circle, DashedPathElement, EmptyElement, Rectangle, Text};
+use plotters::drawing::{DrawingArea, IntoDrawingArea};
+use plotters::style::FontTransform;
+use crate::{date_range, RepoStats, StatType};
+use crate::db::Database;
+use tracing::warn;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+// How daily values are drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ChartType {
+    // Dot with value label per day
+    #[default]
+    Points,
+    // Vertical bar from zero per day
+    Bars,
+    // Daily values connected with a line, no value labels
+    Line,
+}
+
+// Longest window whose points get value labels, more would overlap
+const MAX_LABELED_DAYS: u32 = 31;
+
+// Image format written by render()
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Svg,
+    // Needs the png feature
+    Png,
+}
+
+impl OutputFormat {
+    // File name extension, also selects the image encoder of BitMapBackend
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Svg => "svg",
+            OutputFormat::Png => "png",
+        }
+    }
+}
+
+// Background and text colors, "light" or "dark" on command line and in config
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Light,
+    // For dark web pages and dashboards
+    Dark,
+}
+
+// Annotation lines and labels, stands out from series colors in both themes
+const ANNOTATION_COLOR: RGBColor = RGBColor(220, 50, 47);
+
+// Series colors of the dark theme, readable on its near-black background
+const DARK_PALETTE: [RGBColor; 4] = [
+    RGBColor(100, 181, 246),
+    RGBColor(255, 183, 77),
+    RGBColor(129, 199, 132),
+    RGBColor(240, 98, 146),
+];
+
+impl Theme {
+    fn background(&self) -> RGBColor {
+        match self {
+            Theme::Light => WHITE,
+            Theme::Dark => RGBColor(18, 18, 18),
+        }
+    }
+
+    // Texts, axes and grid lines
+    fn foreground(&self) -> RGBColor {
+        match self {
+            Theme::Light => BLACK,
+            Theme::Dark => WHITE,
+        }
+    }
+}
 
 pub struct ChartGenerator {
     data: HashMap<
@@ -22,6 +108,28 @@ pub struct ChartGenerator {
     title: String,
     // How many days, usually 30
     days: u32,
+    // Last day displayed
+    now_ref: NaiveDate,
+    // Comment added to SVG output
+    metadata: Option<String>,
+    // Font family for all texts
+    font: String,
+    // Series colors, Palette99 if empty
+    colors: Vec<RGBColor>,
+    // Show average per day in legend in addition to total
+    legend_average: bool,
+    // Timezone for displayed dates, UTC if not set
+    utc_offset: Option<FixedOffset>,
+    // strftime format for displayed dates
+    date_format: String,
+    // Vertical marker lines with labels, for example release dates
+    annotations: Vec<(NaiveDate, String)>,
+    // Points or bars
+    chart_type: ChartType,
+    // SVG or PNG for render()
+    output_format: OutputFormat,
+    // Light or dark colors
+    theme: Theme,
 }
 
 impl ChartGenerator {
@@ -31,15 +139,119 @@ impl ChartGenerator {
         renames: HashMap<u8, String>,
         days: u32,
     ) -> Self {
-        Self {
-            title: title,
-            data: Default::default(),
-            renames: renames,
-            counts: Default::default(),
-            width: 640,
-            height: 480,
-            filename: filename,
-            days: days,
+        Self::builder()
+            .title(title)
+            .filename(filename)
+            .renames(renames)
+            .days(days)
+            .build()
+    }
+
+    // Builder with default options
+    pub fn builder() -> ChartGeneratorBuilder {
+        ChartGeneratorBuilder {
+            chart: Self {
+                title: String::new(),
+                data: Default::default(),
+                renames: Default::default(),
+                counts: Default::default(),
+                width: 640,
+                height: 480,
+                filename: PathBuf::from("chart.svg"),
+                days: 30,
+                now_ref: Utc::now().date_naive(),
+                metadata: None,
+                font: String::from("sans-serif"),
+                colors: Vec::new(),
+                legend_average: false,
+                utc_offset: None,
+                date_format: String::from("%Y-%m-%d"),
+                annotations: Vec::new(),
+                chart_type: ChartType::default(),
+                output_format: OutputFormat::default(),
+                theme: Theme::default(),
+            },
+        }
+    }
+
+    // Set output file for render()
+    pub fn set_filename(&mut self, filename: PathBuf) {
+        self.filename = filename;
+    }
+
+    // Set last day displayed, default is current UTC date
+    pub fn set_now_ref(&mut self, now_ref: NaiveDate) {
+        self.now_ref = now_ref;
+    }
+
+    // Set image size in pixels
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    // Set font family
+    pub fn set_font(&mut self, font: String) {
+        self.font = font;
+    }
+
+    // Set series colors, used in order of series
+    pub fn set_colors(&mut self, colors: Vec<RGBColor>) {
+        self.colors = colors;
+    }
+
+    // Show average per day in legend
+    pub fn set_legend_average(&mut self, enabled: bool) {
+        self.legend_average = enabled;
+    }
+
+    // Display dates in a timezone. Storage is bucketed by UTC day,
+    // so each date's start (00:00 UTC) is converted to the timezone.
+    pub fn set_utc_offset(&mut self, offset: Option<FixedOffset>) {
+        self.utc_offset = offset;
+    }
+
+    // Set strftime format for displayed dates, see valid_date_format()
+    pub fn set_date_format(&mut self, format: String) {
+        self.date_format = format;
+    }
+
+    // Set dates marked with vertical lines and labels
+    pub fn set_annotations(&mut self, annotations: Vec<(NaiveDate, String)>) {
+        self.annotations = annotations;
+    }
+
+    // Mark a date with a vertical line and label, for example a release
+    pub fn add_vertical_annotation(&mut self, date: NaiveDate, label: String) {
+        self.annotations.push((date, label));
+    }
+
+    // Draw values as points or bars
+    pub fn set_chart_type(&mut self, t: ChartType) {
+        self.chart_type = t;
+    }
+
+    // Set image format written by render()
+    pub fn set_output_format(&mut self, fmt: OutputFormat) {
+        self.output_format = fmt;
+    }
+
+    // Set background and text colors. Series colors set with set_colors are used as is.
+    pub fn set_theme(&mut self, t: Theme) {
+        self.theme = t;
+    }
+
+    // Format a date for display
+    fn display_date(&self, d: NaiveDate) -> String {
+        match self.utc_offset {
+            None => d.format(&self.date_format).to_string(),
+            Some(offset) => {
+                d.and_time(NaiveTime::MIN)
+                    .and_utc()
+                    .with_timezone(&offset)
+                    .format(&self.date_format)
+                    .to_string()
+            }
         }
     }
 
@@ -59,8 +271,142 @@ impl ChartGenerator {
         self.data.insert(d, data);
     }
 
-    // Render SVG
+    // Set text embedded as a comment in the SVG output, for example generation time and source
+    pub fn set_metadata(&mut self, text: String) {
+        // "--" is not allowed inside XML comments
+        self.metadata = Some(text.replace("--", "- -"));
+    }
+
+    // Render SVG or PNG to file
     pub fn render(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.output_format {
+            OutputFormat::Svg => {
+                let mut f = File::create(&self.filename)?;
+                self.render_to_writer(&mut f)?;
+                f.flush()?;
+
+                Ok(())
+            }
+            #[cfg(feature = "png")]
+            OutputFormat::Png => {
+                let filename = self.filename.clone();
+                self.render_png(&filename)
+            }
+            #[cfg(not(feature = "png"))]
+            OutputFormat::Png => Err("PNG output needs github-stats built with the png feature".into()),
+        }
+    }
+
+    // Render SVG to a writer, for example a HTTP response.
+    // SVG is buffered in memory because metadata comments are added after drawing.
+    pub fn render_to_writer<W: Write>(&mut self, w: &mut W) -> Result<(), Box<dyn Error>> {
+        let svg = self.render_to_string()?;
+        w.write_all(svg.as_bytes())?;
+
+        Ok(())
+    }
+
+    // Render PNG to file
+    #[cfg(feature = "png")]
+    pub fn render_png(&mut self, filename: &Path) -> Result<(), Box<dyn Error>> {
+        let root = BitMapBackend::new(
+            filename,
+            (self.width, self.height),
+        ).into_drawing_area();
+
+        self.draw(root)
+    }
+
+    // Render SVG to a string
+    pub fn render_to_string(&mut self) -> Result<String, Box<dyn Error>> {
+        let mut svg = String::new();
+
+        {
+            let root = SVGBackend::with_string(
+                &mut svg,
+                (self.width, self.height),
+            ).into_drawing_area();
+
+            self.draw(root)?;
+        }
+
+        // Add comments after the opening <svg> tag
+        let pos = svg.find('>').map(|p| p + 1).unwrap_or(0);
+        svg.insert_str(pos, &format!("\n<!-- {}{} -->", HASH_PREFIX, self.content_hash()));
+
+        if let Some(text) = &self.metadata {
+            svg.insert_str(pos, &format!("\n<!-- {} -->", text));
+        }
+
+        Ok(svg)
+    }
+
+    // Hash of everything that affects the rendered chart.
+    // Same hash means the same image, so rendering can be skipped.
+    pub fn content_hash(&self) -> String {
+        let mut h = Fnv1a::new();
+
+        // Axis labels are relative to last day
+        h.write(self.now_ref.to_string().as_bytes());
+        h.write(self.title.as_bytes());
+        h.write(self.font.as_bytes());
+        h.write(&[self.legend_average as u8]);
+        h.write(&[self.chart_type as u8]);
+        h.write(&[self.output_format as u8]);
+        h.write(&[self.theme as u8]);
+        h.write(self.date_format.as_bytes());
+        h.write(&self.utc_offset.map(|o| o.local_minus_utc()).unwrap_or(0).to_le_bytes());
+
+        for n in [self.width, self.height, self.days] {
+            h.write(&n.to_le_bytes());
+        }
+
+        for c in &self.colors {
+            h.write(&[c.0, c.1, c.2]);
+        }
+
+        let mut renames: Vec<_> = self.renames.iter().collect();
+        renames.sort();
+        for (k, v) in renames {
+            h.write(&[*k]);
+            h.write(v.as_bytes());
+        }
+
+        for (d, label) in &self.annotations {
+            h.write(d.to_string().as_bytes());
+            h.write(label.as_bytes());
+        }
+
+        let mut dates: Vec<&NaiveDate> = self.data.keys().collect();
+        dates.sort();
+        for d in dates {
+            h.write(d.to_string().as_bytes());
+
+            let mut vals: Vec<_> = self.data[d].iter().collect();
+            vals.sort();
+            for (k, v) in vals {
+                h.write(&[*k]);
+                h.write(&v.to_le_bytes());
+            }
+        }
+
+        format!("{:016x}", h.finish())
+    }
+
+    // Draw chart to a drawing area
+    fn draw<DB: DrawingBackend>(
+        &mut self,
+        root: DrawingArea<DB, Shift>,
+    ) -> Result<(), Box<dyn Error>>
+        where
+            DB::ErrorType: 'static,
+    {
+        let future = self.data.keys().filter(|d| **d > self.now_ref).count();
+
+        if future > 0 {
+            warn!("{} days of chart data are after {} and not displayed, check system clock", future, self.now_ref);
+        }
+
         let mut max_y: u64 = 0;
 
         for (_, vals) in self.data.clone() {
@@ -76,41 +422,51 @@ impl ChartGenerator {
             max_y = 10;
         }
 
-        let root = SVGBackend::new(
-            self.filename.as_path(),
-            (self.width, self.height),
-        ).into_drawing_area();
+        // Top of y axis, rounded to nearest ten
+        let y_top = max_y.div_ceil(10) * 10;
 
-        root.fill(&WHITE)?;
+        let fg = self.theme.foreground();
+        root.fill(&self.theme.background())?;
         let root = root.margin(5, 5, 20, 30);
 
-        let now_naive = Utc::now().date_naive();
+        // x is days since first_date, so time runs left to right and each label is the date at x
+        let (first_date, last_date) = date_range(self.now_ref, self.days);
 
         // construct chart context
         let mut chart = ChartBuilder::on(&root)
             // Set the caption of the chart
             .caption(
                 &self.title,
-                ("sans-serif", 30).into_font(),
+                (self.font.as_str(), 30).into_font().color(&fg),
             )
             // Set the size of the label region
             .x_label_area_size(35)// days
             .y_label_area_size(30)// counts
             .build_cartesian_2d(
-                0u32..self.days, // days 0-29 / 1-30
-                0u64..((max_y + 9) / 10 * 10), // count of views / clones rounded to nearest ten
+                0u32..self.days, // days since first_date, 0-29
+                0u64..y_top, // count of views / clones rounded to nearest ten
             )?
             ;
 
 
         // draw a mesh
-        chart
-            .configure_mesh()
+        let mut mesh = chart.configure_mesh();
+
+        if self.theme == Theme::Dark {
+            // Defaults are black on white
+            mesh
+                .axis_style(fg)
+                .bold_line_style(fg.mix(0.3))
+                .light_line_style(fg.mix(0.15))
+                .label_style((self.font.as_str(), 12).into_font().color(&fg));
+        }
+
+        mesh
             .x_desc(
                 format!(
-                    "Dates {:?} - {:?}",
-                    now_naive,
-                    now_naive.clone().checked_sub_days(Days::new(self.days as u64)).expect("date error")
+                    "Dates {} - {}",
+                    self.display_date(first_date),
+                    self.display_date(last_date)
                 )
             )
             .y_desc("Count")
@@ -135,12 +491,11 @@ impl ChartGenerator {
             .x_label_formatter(
                 &|x| {
                     // Date
-                    format!(
-                        "{:?}",
-                        now_naive.checked_sub_days(
-                            Days::new((*x) as u64)
-                        ).expect("??")
-                    )
+                    // End of axis is the day after last_date
+                    match first_date.checked_add_days(Days::new((*x) as u64)) {
+                        Some(d) if d <= last_date => { self.display_date(d) }
+                        _ => { String::new() }
+                    }
                 }
             )
             .draw()?;
@@ -150,52 +505,87 @@ impl ChartGenerator {
             // Add empty if missing
             self.counts.entry(typeid).or_insert(0);
 
-            let mut now = now_naive.clone().to_owned();
             let mut data: Vec<(u32, u64)> = vec![];
 
-            // Last N days of data
+            // Last N days of data, oldest first
             for day_index in 0..self.days {
-                match self.data.get(&now) {
-                    None => { data.push((day_index, 0)) }
+                let day = match first_date.checked_add_days(Days::new(day_index as u64)) {
+                    // Range starts at the earliest representable date and is shorter than N days
+                    Some(d) if d <= last_date => { d }
+                    _ => { break; }
+                };
+
+                let val = match self.data.get(&day) {
+                    None => { 0 }
                     Some(d) => {
-                        let val = match d.get(&typeid) {
+                        match d.get(&typeid) {
                             None => { 0 }
                             Some(v) => { *v }
-                        };
-
-                        data.push((day_index, val));
+                        }
                     }
                 };
 
-                now = match now.checked_sub_days(Days::new(1)) {
-                    None => { panic!("invalid date"); }
-                    Some(d) => { d }
-                };
+                data.push((day_index, val));
             }
 
-            let color = Palette99::pick(typeid as usize).mix(0.9);
+            let color = if !self.colors.is_empty() {
+                self.colors[typeid as usize % self.colors.len()].mix(0.9)
+            } else if self.theme == Theme::Dark {
+                DARK_PALETTE[typeid as usize % DARK_PALETTE.len()].mix(0.9)
+            } else {
+                Palette99::pick(typeid as usize).mix(0.9)
+            };
+            let font = self.font.clone();
+            let labeled = self.days <= MAX_LABELED_DAYS;
 
-            // draw points
-            chart
-                .draw_series(
+            let series = match self.chart_type {
+                // draw points
+                ChartType::Points => chart.draw_series(
                     PointSeries::of_element(
                         data,
                         5,
                         color.clone().to_rgba(),
                         &|c, s, st| {
-                            return EmptyElement::at(c)
+                            // Empty label for long windows
+                            let text = if labeled { format!("{}", c.1) } else { String::new() };
+
+                            EmptyElement::at(c)
                                 + Circle::new((0, 0), s, st.filled()) // At this point, the new pixel coordinate is established
-                                + Text::new(format!("{}", c.1), (-5, -18), ("sans-serif", 15).into_font());
+                                + Text::new(text, (-5, -18), (font.as_str(), 15).into_font().color(&fg))
                         },
                     )
-                )?
+                )?,
+                // draw bars, each filling its day with a small gap between days
+                ChartType::Bars => chart.draw_series(
+                    data.into_iter().map(|(x, y)| {
+                        let mut bar = Rectangle::new(
+                            [(x, 0), (x + 1, y)],
+                            color.clone().to_rgba().filled(),
+                        );
+                        bar.set_margin(0, 0, 1, 1);
+                        bar
+                    })
+                )?,
+                // draw line
+                ChartType::Line => chart.draw_series(
+                    LineSeries::new(data, color.clone().to_rgba().stroke_width(2))
+                )?,
+            };
+
+            series
                 .label(
                     // Add legend name
                     match self.renames.get(&typeid) {
                         None => { String::from("?") }
                         Some(n) => {
                             // Add total counts
-                            format!("{} ({})", n, self.counts[&typeid])
+                            let total = self.counts[&typeid];
+
+                            if self.legend_average && self.days > 0 {
+                                format!("{}: total {}, avg {:.1}/day", n, total, total as f64 / self.days as f64)
+                            } else {
+                                format!("{} ({})", n, total)
+                            }
                         }
                     }
                 )
@@ -207,6 +597,41 @@ impl ChartGenerator {
                 );
         } // /for
 
+        // Annotations as vertical dashed lines
+        for (date, label) in &self.annotations {
+            let day_index = (*date - first_date).num_days();
+
+            if day_index < 0 || *date > last_date {
+                // Outside of displayed range
+                continue;
+            }
+
+            let x = day_index as u32;
+
+            chart.draw_series([
+                DashedPathElement::new(
+                    [(x, 0), (x, y_top)],
+                    6,
+                    4,
+                    ANNOTATION_COLOR.mix(0.8).stroke_width(1),
+                )
+            ])?;
+
+            // Label reads downwards from the top, right of the line.
+            // Rotated text extends left of its position, so it's offset by its height.
+            chart.draw_series([
+                EmptyElement::at((x, y_top))
+                    + Text::new(
+                        label.clone(),
+                        (15, 3),
+                        (self.font.as_str(), 12)
+                            .into_font()
+                            .transform(FontTransform::Rotate90)
+                            .color(&ANNOTATION_COLOR),
+                    )
+            ])?;
+        }
+
         // Legend
         chart
             .configure_series_labels()
@@ -215,7 +640,7 @@ impl ChartGenerator {
             .legend_area_size(0)
             .border_style(BLUE)
             .background_style(BLUE.mix(0.1))
-            .label_font(("sans-serif", 20))
+            .label_font((self.font.as_str(), 20).into_font().color(&fg))
             .draw()?
         ;
 
@@ -230,3 +655,323 @@ impl ChartGenerator {
         self.counts = Default::default();
     }
 }
+
+// Chart options for chart_from_stats and generate_chart
+#[derive(Clone, Debug)]
+pub struct ChartOptions {
+    pub width: u32,
+    pub height: u32,
+    // Font family, sans-serif if not set
+    pub font: Option<String>,
+    // Series colors, Palette99 if empty
+    pub colors: Vec<RGBColor>,
+    // Show average per day in legend
+    pub legend_average: bool,
+    // Timezone for displayed dates, UTC if not set
+    pub utc_offset: Option<FixedOffset>,
+    // strftime format for displayed dates
+    pub date_format: String,
+    // Vertical marker lines with labels
+    pub annotations: Vec<(NaiveDate, String)>,
+    // Display name used in caption instead of repository name
+    pub title: Option<String>,
+    // Points or bars
+    pub chart_type: ChartType,
+    // Image format written by render()
+    pub output_format: OutputFormat,
+    // Light or dark colors
+    pub theme: Theme,
+}
+
+impl Default for ChartOptions {
+    fn default() -> Self {
+        Self {
+            width: 640,
+            height: 480,
+            font: None,
+            colors: Vec::new(),
+            legend_average: false,
+            utc_offset: None,
+            date_format: String::from("%Y-%m-%d"),
+            annotations: Vec::new(),
+            title: None,
+            chart_type: ChartType::default(),
+            output_format: OutputFormat::default(),
+            theme: Theme::default(),
+        }
+    }
+}
+
+// Chart of one traffic type from stored stats, days ending at now_ref
+pub fn chart_from_stats(
+    stats: &[RepoStats],
+    owner: &str,
+    repo_name: &str,
+    stat_type: StatType,
+    now_ref: NaiveDate,
+    days: u32,
+    opts: &ChartOptions,
+) -> ChartGenerator {
+    let n = stat_type.name();
+    let (first_date, last_date) = date_range(now_ref, days);
+
+    // Legend
+    let renames: HashMap<u8, String> = [
+        (0, "Count".to_string()),
+        (1, "Unique".to_string()),
+    ].iter().cloned().collect();
+
+    let mut builder = ChartGenerator::builder()
+        .title(format!("GitHub {} for {}", n, opts.title.as_deref().unwrap_or(repo_name)))
+        .renames(renames)
+        .days(days)
+        .now_ref(now_ref)
+        .size(opts.width, opts.height)
+        .colors(opts.colors.clone())
+        .legend_average(opts.legend_average)
+        .utc_offset(opts.utc_offset)
+        .date_format(opts.date_format.clone())
+        .annotations(opts.annotations.clone())
+        .chart_type(opts.chart_type)
+        .output_format(opts.output_format)
+        .theme(opts.theme)
+        .metadata(format!(
+            "generated by github-stats v{} at {} for {}/{}, range {} - {}",
+            env!("CARGO_PKG_VERSION"),
+            Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+            owner,
+            repo_name,
+            first_date,
+            last_date,
+        ));
+
+    if let Some(font) = &opts.font {
+        builder = builder.font(font.clone());
+    }
+
+    let mut chart_gen = builder.build();
+
+    // Add clone or view count(s)
+    for item in stats {
+        let s = match item.get(stat_type) {
+            None => { continue; }
+            Some(s) => { s }
+        };
+
+        let m: HashMap<u8, u64> = [
+            (0, s.count),
+            (1, s.uniques),
+        ].iter().cloned().collect();
+
+        chart_gen.add(item.date, m);
+    }
+
+    chart_gen
+}
+
+// Render SVG chart of one repository's traffic type from database
+pub fn generate_chart(
+    db: &Database,
+    owner: &str,
+    repo_name: &str,
+    stat_type: StatType,
+    now_ref: NaiveDate,
+    days: u32,
+    opts: &ChartOptions,
+) -> Result<String, Box<dyn Error>> {
+    let stats = db.get_repo_stats(owner, repo_name, now_ref, days)?;
+
+    chart_from_stats(&stats, owner, repo_name, stat_type, now_ref, days, opts)
+        .render_to_string()
+}
+
+// Parse timezone as UTC offset: "UTC", "Z", "+03:00", "-0500" or "+2"
+pub fn parse_utc_offset(s: &str) -> Option<FixedOffset> {
+    let s = s.trim();
+
+    if s.eq_ignore_ascii_case("utc") || s == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+
+    let sign = match s.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+
+    let digits: String = s[1..].chars().filter(|c| *c != ':').collect();
+
+    let (hours, minutes) = match digits.len() {
+        1 | 2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (digits[0..2].parse::<i32>().ok()?, digits[2..4].parse::<i32>().ok()?),
+        _ => return None,
+    };
+
+    if minutes >= 60 {
+        return None;
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+// Check that a strftime format can be used for dates
+pub fn valid_date_format(format: &str) -> bool {
+    !StrftimeItems::new(format).any(|i| i == Item::Error)
+}
+
+// Prefix of the content hash comment in SVG output
+const HASH_PREFIX: &str = "github-stats-hash: ";
+
+// Get content hash embedded in a previously rendered SVG
+pub fn embedded_hash(svg: &str) -> Option<String> {
+    let start = svg.find(HASH_PREFIX)? + HASH_PREFIX.len();
+    let len = svg[start..].find(" -->")?;
+
+    Some(svg[start..start + len].to_string())
+}
+
+// FNV-1a 64-bit hash, stable across builds and platforms unlike std's DefaultHasher
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+
+        // Field separator so that ("ab", "c") and ("a", "bc") differ
+        self.0 ^= 0xff;
+        self.0 = self.0.wrapping_mul(0x100000001b3);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+// Builder for ChartGenerator options
+pub struct ChartGeneratorBuilder {
+    chart: ChartGenerator,
+}
+
+impl ChartGeneratorBuilder {
+    // Chart caption
+    pub fn title(mut self, title: String) -> Self {
+        self.chart.title = title;
+        self
+    }
+
+    // Output file for render()
+    pub fn filename(mut self, filename: PathBuf) -> Self {
+        self.chart.filename = filename;
+        self
+    }
+
+    // Legend names for series
+    pub fn renames(mut self, renames: HashMap<u8, String>) -> Self {
+        self.chart.renames = renames;
+        self
+    }
+
+    // How many days to display
+    pub fn days(mut self, days: u32) -> Self {
+        self.chart.days = days;
+        self
+    }
+
+    // Last day displayed
+    pub fn now_ref(mut self, now_ref: NaiveDate) -> Self {
+        self.chart.set_now_ref(now_ref);
+        self
+    }
+
+    // Image size in pixels
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.chart.set_size(width, height);
+        self
+    }
+
+    // Font family
+    pub fn font(mut self, font: String) -> Self {
+        self.chart.set_font(font);
+        self
+    }
+
+    // Series colors
+    pub fn colors(mut self, colors: Vec<RGBColor>) -> Self {
+        self.chart.set_colors(colors);
+        self
+    }
+
+    // Show average per day in legend
+    pub fn legend_average(mut self, enabled: bool) -> Self {
+        self.chart.set_legend_average(enabled);
+        self
+    }
+
+    // Timezone for displayed dates
+    pub fn utc_offset(mut self, offset: Option<FixedOffset>) -> Self {
+        self.chart.set_utc_offset(offset);
+        self
+    }
+
+    // strftime format for displayed dates
+    pub fn date_format(mut self, format: String) -> Self {
+        self.chart.set_date_format(format);
+        self
+    }
+
+    // Dates marked with vertical lines and labels
+    pub fn annotations(mut self, annotations: Vec<(NaiveDate, String)>) -> Self {
+        self.chart.set_annotations(annotations);
+        self
+    }
+
+    // Points or bars
+    pub fn chart_type(mut self, t: ChartType) -> Self {
+        self.chart.set_chart_type(t);
+        self
+    }
+
+    // Image format written by render()
+    pub fn output_format(mut self, fmt: OutputFormat) -> Self {
+        self.chart.set_output_format(fmt);
+        self
+    }
+
+    // Light or dark colors
+    pub fn theme(mut self, t: Theme) -> Self {
+        self.chart.set_theme(t);
+        self
+    }
+
+    // Comment embedded in SVG output
+    pub fn metadata(mut self, text: String) -> Self {
+        self.chart.set_metadata(text);
+        self
+    }
+
+    pub fn build(self) -> ChartGenerator {
+        self.chart
+    }
+}
+
+// Parse "#rrggbb" or "rrggbb" color
+pub fn parse_color(s: &str) -> Option<RGBColor> {
+    let hex = s.trim().trim_start_matches('#');
+
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let r = u8::from_str_radix(&hex[0..2], 16).ok()?;
+    let g = u8::from_str_radix(&hex[2..4], 16).ok()?;
+    let b = u8::from_str_radix(&hex[4..6], 16).ok()?;
+
+    Some(RGBColor(r, g, b))
+}
@@ -1,71 +1,423 @@
-use rusqlite::Connection;
-use std::path::PathBuf;
-use chrono::{Datelike, Days, NaiveDate};
-use crate::github::DayStats;
-use crate::{Repo, RepoStats, Stats, StatType};
+use rusqlite::{Connection, ErrorCode, OpenFlags, OptionalExtension, Params};
+use rusqlite::types::Value;
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use chrono::{Datelike, DateTime, NaiveDate, Utc};
+use crate::github::{DayStats, PopularPath, ReferrerStats};
+use tracing::warn;
+use crate::{date_range, GithubStatsError, ReferrerTotal, Repo, RepoStats, RepoTotals, RetryPolicy, Stats, StatType};
 
 pub struct Database {
     conn: Connection,
+    // Retries for writes while another process holds the database lock
+    retry: RetryPolicy,
 }
 
+// Schema changes in order, statement at index N upgrades schema version N to N+1.
+// Version is stored in PRAGMA user_version. Databases created before versioning have
+// version 0 but might already have some of the tables, so these must be idempotent.
+// See https://www.sqlite.org/lang_createtable.html
+const MIGRATIONS: &[&str] = &[
+    // 1: Daily traffic
+    r#"
+      CREATE TABLE IF NOT EXISTS traffic (
+        y INTEGER NOT NULL,
+        m INTEGER NOT NULL,
+        d INTEGER NOT NULL,
+
+        owner TEXT NOT NULL,
+        repo TEXT NOT NULL,
+
+        c_count  INTEGER NOT NULL DEFAULT 0,
+        c_uniq   INTEGER NOT NULL DEFAULT 0,
+
+        v_count  INTEGER NOT NULL DEFAULT 0,
+        v_uniq   INTEGER NOT NULL DEFAULT 0,
+
+        PRIMARY KEY (y, m, d, owner, repo)
+      )"#,
+    // 2: Daily snapshots of popular referrers
+    r#"
+      CREATE TABLE IF NOT EXISTS referrers (
+        fetched_date TEXT NOT NULL,
+
+        owner TEXT NOT NULL,
+        repo TEXT NOT NULL,
+        referrer TEXT NOT NULL,
+
+        count    INTEGER NOT NULL DEFAULT 0,
+        uniques  INTEGER NOT NULL DEFAULT 0,
+
+        PRIMARY KEY (fetched_date, owner, repo, referrer)
+      )"#,
+    // 3: When each repository was last fetched from GitHub
+    r#"
+      CREATE TABLE IF NOT EXISTS fetch_log (
+        owner TEXT NOT NULL,
+        repo TEXT NOT NULL,
+
+        fetched_at TEXT NOT NULL,
+
+        PRIMARY KEY (owner, repo)
+      )"#,
+    // 4: Repositories whose traffic changed during the latest fetch
+    r#"
+      CREATE TABLE IF NOT EXISTS changed_repos (
+        owner TEXT NOT NULL,
+        repo TEXT NOT NULL,
+
+        PRIMARY KEY (owner, repo)
+      )"#,
+    // 5: ISO 8601 date of traffic rows for range queries, written by update_traffic.
+    // Not a generated column, those need SQLite 3.31 and the system library may be older.
+    r#"
+      ALTER TABLE traffic ADD COLUMN date TEXT;
+      UPDATE traffic SET date = printf('%04d-%02d-%02d', y, m, d);
+      CREATE INDEX IF NOT EXISTS traffic_repo_date ON traffic (owner, repo, date)
+      "#,
+    // 6: Daily snapshots of star counts, GitHub has no star history
+    r#"
+      CREATE TABLE IF NOT EXISTS stars (
+        date TEXT NOT NULL,
+
+        owner TEXT NOT NULL,
+        repo TEXT NOT NULL,
+
+        count INTEGER NOT NULL DEFAULT 0,
+
+        PRIMARY KEY (date, owner, repo)
+      )"#,
+    // 7: Daily snapshots of popular content paths
+    r#"
+      CREATE TABLE IF NOT EXISTS popular_paths (
+        fetched_date TEXT NOT NULL,
+
+        owner TEXT NOT NULL,
+        repo TEXT NOT NULL,
+        path TEXT NOT NULL,
+        title TEXT NOT NULL DEFAULT '',
+
+        count    INTEGER NOT NULL DEFAULT 0,
+        uniques  INTEGER NOT NULL DEFAULT 0,
+
+        PRIMARY KEY (fetched_date, owner, repo, path)
+      )"#,
+];
+
+// Schema version of databases created or upgraded by this version
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+// How long a statement waits for a lock held by another connection before failing as busy
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl Database {
-    pub fn new(database_file: &PathBuf) -> Self {
-        let conn = Connection::open(database_file)
-            .expect("couldn't connect to local database");
+    // Open or create database and upgrade its schema
+    pub fn new(database_file: &PathBuf) -> Result<Self, rusqlite::Error> {
+        let conn = Connection::open(database_file)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+
+        // Readers don't block the writer and the writer doesn't block readers,
+        // so charts can be generated while fetch is updating the database.
+        // https://www.sqlite.org/wal.html
+        let mode: String = conn.query_row("PRAGMA journal_mode=WAL", [], |row| row.get(0))?;
+
+        if !mode.eq_ignore_ascii_case("wal") {
+            // For example a network filesystem without shared memory support
+            warn!("couldn't enable WAL journal mode for {}, using {}", database_file.display(), mode);
+        }
+
+        let db = Self {
+            conn,
+            retry: RetryPolicy::default(),
+        };
+
+        db.migrate()?;
+
+        Ok(db)
+    }
+
+    // Apply migrations newer than database's schema version, returns how many were applied
+    pub fn migrate(&self) -> rusqlite::Result<u32> {
+        let version = self.schema_version()?;
+
+        if version > SCHEMA_VERSION {
+            warn!("database schema version {} is newer than supported version {}", version, SCHEMA_VERSION);
+            return Ok(0);
+        }
+
+        for (i, sql) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            // Each step and its version bump commit together
+            self.conn.execute_batch(&format!(
+                "BEGIN; {}; PRAGMA user_version = {}; COMMIT;",
+                sql, i + 1,
+            )).inspect_err(|_| {
+                let _ = self.conn.execute_batch("ROLLBACK");
+            })?;
+        }
+
+        Ok(SCHEMA_VERSION - version)
+    }
+
+    // Result of PRAGMA integrity_check, ["ok"] if database is intact
+    // https://www.sqlite.org/pragma.html#pragma_integrity_check
+    pub fn check_integrity(&self) -> rusqlite::Result<Vec<String>> {
+        self.pragma_lines("integrity_check")
+    }
+
+    // Result of PRAGMA quick_check, faster than integrity_check but doesn't check indexes against tables
+    pub fn quick_check(&self) -> rusqlite::Result<Vec<String>> {
+        self.pragma_lines("quick_check")
+    }
+
+    // Single column output of a PRAGMA
+    fn pragma_lines(&self, pragma: &str) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(&format!("PRAGMA {}", pragma))?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+
+        rows.collect()
+    }
+
+    // Schema version, 0 for databases created before versioning
+    pub fn schema_version(&self) -> rusqlite::Result<u32> {
+        self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+    }
+
+    // Back up database file and create a new empty one in its place.
+    // The old file isn't opened, so a corrupted database can be replaced too.
+    // Returns the new database and the backup file name.
+    pub fn recreate(database_file: &PathBuf) -> Result<(Self, PathBuf), GithubStatsError> {
+        let mut backup_name = database_file.clone().into_os_string();
+        backup_name.push(format!(".bak-{}", Utc::now().format("%Y%m%d%H%M%S")));
+        let backup = PathBuf::from(backup_name);
+
+        // Journal or WAL left behind by a crash belongs to the old database,
+        // SQLite would otherwise apply it to the new one
+        let backup_siblings = Self::sibling_files(&backup);
+
+        for (file, backup_file) in Self::sibling_files(database_file).iter().zip(backup_siblings.iter()) {
+            if file.exists() {
+                fs::copy(file, backup_file)?;
+                fs::remove_file(file)?;
+            }
+        }
+
+        let db = Self::new(database_file)?;
+
+        Ok((db, backup))
+    }
+
+    // Database file followed by the rollback journal, WAL and shared memory files SQLite keeps next to it
+    pub fn sibling_files(database_file: &Path) -> Vec<PathBuf> {
+        let mut res = vec![database_file.to_path_buf()];
+
+        for suffix in ["-journal", "-wal", "-shm"] {
+            let mut name = database_file.as_os_str().to_os_string();
+            name.push(suffix);
+            res.push(PathBuf::from(name));
+        }
+
+        res
+    }
+
+    // Check that database path can be a database file
+    pub fn validate_path(database_file: &Path) -> Result<(), GithubStatsError> {
+        if database_file.as_os_str().is_empty() {
+            Err(GithubStatsError::InvalidInput(String::from("database file name is empty")))?
+        }
 
-        // See https://www.sqlite.org/lang_createtable.html
-        conn.execute(r#"
-          CREATE TABLE IF NOT EXISTS traffic (
-            y INTEGER NOT NULL,
-            m INTEGER NOT NULL,
-            d INTEGER NOT NULL,
+        if database_file.is_dir() {
+            Err(GithubStatsError::InvalidInput(format!("database path {} is a directory, expected a file", database_file.display())))?
+        }
+
+        if database_file.file_name().is_none() {
+            Err(GithubStatsError::InvalidInput(format!("database path {} doesn't end in a file name", database_file.display())))?
+        }
+
+        let known = database_file
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| ["db", "sqlite", "sqlite3", "db3"].contains(&e.to_lowercase().as_str()))
+            .unwrap_or(false);
 
-            owner TEXT NOT NULL,
-            repo TEXT NOT NULL,
+        if !known {
+            warn!("database file {} has no .db, .sqlite, .sqlite3 or .db3 extension", database_file.display());
+        }
 
-            c_count  INTEGER NOT NULL DEFAULT 0,
-            c_uniq   INTEGER NOT NULL DEFAULT 0,
+        Ok(())
+    }
 
-            v_count  INTEGER NOT NULL DEFAULT 0,
-            v_uniq   INTEGER NOT NULL DEFAULT 0,
+    // Open existing database for reading only
+    pub fn open_read_only(database_file: &PathBuf) -> rusqlite::Result<Self> {
+        let conn = Connection::open_with_flags(
+            database_file,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
 
-            PRIMARY KEY (y, m, d, owner, repo)
-          )"#, (), // empty list of parameters.
-        ).expect("couldn't create table: traffic");
+        Ok(Self {
+            conn,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    // How to retry writes which failed because the database was busy
+    pub fn set_retry(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    // Execute a statement, retrying while the database is busy (SQLITE_BUSY)
+    fn execute<P: Params + Clone>(&self, sql: &str, params: P) -> rusqlite::Result<usize> {
+        let mut retry = 0;
+
+        loop {
+            match self.conn.execute(sql, params.clone()) {
+                Err(rusqlite::Error::SqliteFailure(e, _))
+                if e.code == ErrorCode::DatabaseBusy && retry + 1 < self.retry.attempts => {
+                    let delay = self.retry.delay(retry);
+                    warn!("database is busy, retrying in {:?}", delay);
+                    thread::sleep(delay);
+                    retry += 1;
+                }
+                res => { return res; }
+            }
+        }
+    }
+
+    // Run a read-only SQL statement, returns column names and rows as text
+    pub fn query(&self, sql: &str) -> rusqlite::Result<(Vec<String>, Vec<Vec<String>>)> {
+        let mut stmt = self.conn.prepare(sql)?;
+
+        if !stmt.readonly() {
+            return Err(rusqlite::Error::InvalidQuery);
+        }
+
+        let columns: Vec<String> = stmt
+            .column_names()
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        let mut q = stmt.query([])?;
+
+        while let Some(row) = q.next()? {
+            let mut r: Vec<String> = Vec::new();
+
+            for i in 0..columns.len() {
+                let v = match row.get::<_, Value>(i)? {
+                    Value::Null => String::new(),
+                    Value::Integer(n) => n.to_string(),
+                    Value::Real(f) => f.to_string(),
+                    Value::Text(t) => t,
+                    Value::Blob(b) => format!("<{} bytes>", b.len()),
+                };
+
+                r.push(v);
+            }
+
+            rows.push(r);
+        }
+
+        Ok((columns, rows))
+    }
 
-        Self {
-            conn: conn,
+    // Wrap database with an in-memory cache for repository stats queries
+    pub fn with_stats_cache(self) -> CachedDatabase {
+        CachedDatabase {
+            db: self,
+            cache: Default::default(),
+            order: Default::default(),
+            seen_changes: Cell::new(None),
         }
     }
 
-    // Update traffic stats
+    // Marker of stored data: rows changed through this connection and commits of other connections.
+    // Stays the same until data is written, whichever method or process writes it.
+    // See https://www.sqlite.org/lang_corefunc.html#total_changes and
+    // https://www.sqlite.org/pragma.html#pragma_data_version
+    pub fn change_marker(&self) -> rusqlite::Result<(i64, i64)> {
+        self.conn.query_row(
+            "SELECT total_changes(), (SELECT data_version FROM pragma_data_version)",
+            [], |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+
+    // Run f in one transaction, committed if f succeeds and rolled back if it fails.
+    // Writes made inside it, such as update_traffic, don't start transactions of their own,
+    // so everything stored for a repository is committed at once.
+    pub fn transaction<T, E>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<T, E>
+    where
+        E: From<rusqlite::Error>,
+    {
+        // Rolled back when dropped without commit
+        let tx = self.conn.unchecked_transaction()?;
+        let res = f()?;
+        tx.commit()?;
+
+        Ok(res)
+    }
+
+    // Update traffic stats, returns how many days were added or changed.
+    // All days are written in one transaction, nothing is stored if any write fails.
     pub fn update_traffic(
         &self,
         stat_type: StatType,
         owner: &str,
         repo: &str,
         stats: Vec<DayStats>,
-    ) {
+    ) -> rusqlite::Result<usize> {
+        // Referrers are snapshots of the whole window, not daily traffic
+        if stat_type == StatType::Referrers {
+            return Ok(0);
+        }
+
+        // Rolled back when dropped without commit.
+        // Inside Database::transaction the outer transaction is used instead.
+        let tx = match self.conn.is_autocommit() {
+            true => Some(self.conn.unchecked_transaction()?),
+            false => None,
+        };
+
+        let mut changed: usize = 0;
+        let today = Utc::now().date_naive();
+
         for stat in stats {
+            // Future days would be outside of every date range, most likely clock skew
+            if stat.timestamp.date_naive() > today {
+                warn!(
+                    "skipping {} of {}/{} for {}, date is after current date {}, check system clock",
+                    stat_type.name(), owner, repo, stat.timestamp.date_naive(), today,
+                );
+                continue;
+            }
+
             // See https://www.sqlite.org/lang_insert.html
             // Add empty row
-            self.conn.execute(
+            let inserted = self.execute(
                 r#"INSERT OR IGNORE INTO
                      traffic
-                     (y,  m,  d,  owner, repo) VALUES
-                     (?1, ?2, ?3, ?4,    ?5)
+                     (y,  m,  d,  date, owner, repo) VALUES
+                     (?1, ?2, ?3, ?4,   ?5,    ?6)
                      "#,
                 (
                     stat.timestamp.year(), stat.timestamp.month(), stat.timestamp.day(),
+                    stat.timestamp.date_naive(),
                     &owner,
                     &repo,
                 ),
-            ).expect("couldn't insert into traffic table");
+            )?;
 
-            match stat_type {
+            // Only rows with different values are written
+            let updated = match stat_type {
                 StatType::Clones => {
                     // https://www.sqlite.org/lang_update.html
-                    self.conn.execute(
+                    self.execute(
                         r#"UPDATE
                      traffic
                      SET
@@ -74,6 +426,7 @@ impl Database {
                      WHERE
                        y=?1 AND m=?2 AND d=?3
                        AND owner=?4 AND repo=?5
+                       AND (c_count!=?6 OR c_uniq!=?7)
                      "#,
                         (
                             stat.timestamp.year(),
@@ -84,11 +437,11 @@ impl Database {
                             stat.count,
                             stat.uniques,
                         ),
-                    ).expect("couldn't update traffic table: clones");
+                    )?
                 }
                 StatType::Views => {
                     // https://www.sqlite.org/lang_update.html
-                    self.conn.execute(
+                    self.execute(
                         r#"UPDATE
                      traffic
                      SET
@@ -97,6 +450,7 @@ impl Database {
                      WHERE
                        y=?1 AND m=?2 AND d=?3
                        AND owner=?4 AND repo=?5
+                       AND (v_count!=?6 OR v_uniq!=?7)
                      "#,
                         (
                             stat.timestamp.year(),
@@ -107,10 +461,54 @@ impl Database {
                             stat.count,
                             stat.uniques,
                         ),
-                    ).expect("couldn't update traffic table: views");
+                    )?
                 }
+                // Returned early above
+                StatType::Referrers => 0,
+            };
+
+            if inserted > 0 || updated > 0 {
+                changed += 1;
             }
         }
+
+        if let Some(tx) = tx {
+            tx.commit()?;
+        }
+
+        Ok(changed)
+    }
+
+    // Stored days after given date, caused by clock skew or bad input
+    pub fn future_dates(&self, now_ref: NaiveDate) -> rusqlite::Result<Vec<(Repo, NaiveDate)>> {
+        let mut stmt = self.conn.prepare(
+            r#"SELECT
+              owner, repo, date
+            FROM traffic
+            WHERE
+              date > DATE(?1)
+            ORDER BY owner, repo, date
+            "#,
+        )?;
+
+        let mut res: Vec<(Repo, NaiveDate)> = Vec::new();
+
+        let items = stmt.query_map(
+            [now_ref], |row| {
+                Ok((
+                    Repo {
+                        owner: row.get(0)?,
+                        name: row.get(1)?,
+                    },
+                    row.get(2)?,
+                ))
+            })?;
+
+        for item in items {
+            res.push(item?);
+        }
+
+        Ok(res)
     }
 
     // Get list of repositories
@@ -153,29 +551,25 @@ impl Database {
 
         let mut stmt = self.conn.prepare(
             r#"SELECT
-              DATE(printf('%04d-%02d-%02d', y,m,d)) date,
+              date,
               v_count, v_uniq,
               c_count, c_uniq
             FROM traffic
             WHERE
-              owner=?1 AND repo=?2 AND date >= DATE(?3)
+              owner=?1 AND repo=?2 AND date >= DATE(?3) AND date <= DATE(?4)
             GROUP BY date
             ORDER BY date DESC
-            LIMIT ?4
             "#,
         )?;
 
-        // Calculate last date in range
-        let days_ago = now_ref.checked_sub_days(
-            Days::new(days as u64)
-        ).unwrap();
+        let (first, last) = date_range(now_ref, days);
 
         let items = stmt.query_map(
-            (owner, repo_name, days_ago, days), |row| {
+            (owner, repo_name, first, last), |row| {
                 let date: NaiveDate = row.get(0)?;
 
                 Ok(RepoStats {
-                    date: date,
+                    date,
                     views: Stats {
                         count: row.get(1)?,
                         uniques: row.get(2)?,
@@ -194,6 +588,712 @@ impl Database {
         Ok(res)
     }
 
+    // Write traffic rows of all repositories as CSV, optionally limited to dates from..=to.
+    // Rows are written while they are read, so the whole table is never in memory.
+    // Owner and repository names can't contain commas or quotes, so fields aren't quoted.
+    // Returns number of rows written.
+    pub fn export_csv(
+        &self,
+        writer: &mut dyn Write,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<usize, GithubStatsError> {
+        let mut stmt = self.conn.prepare(
+            r#"SELECT
+              date, owner, repo, v_count, v_uniq, c_count, c_uniq
+            FROM traffic
+            WHERE
+              (?1 IS NULL OR date >= ?1)
+              AND (?2 IS NULL OR date <= ?2)
+            ORDER BY date, owner, repo
+            "#,
+        )?;
+
+        writeln!(writer, "date,owner,repo,v_count,v_uniq,c_count,c_uniq")?;
+
+        let mut rows = stmt.query((from, to))?;
+        let mut written: usize = 0;
+
+        while let Some(row) = rows.next()? {
+            let date: String = row.get(0)?;
+            let owner: String = row.get(1)?;
+            let repo: String = row.get(2)?;
+            let (v_count, v_uniq, c_count, c_uniq): (u64, u64, u64, u64) = (row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?);
+
+            writeln!(writer, "{},{},{},{},{},{},{}", date, owner, repo, v_count, v_uniq, c_count, c_uniq)?;
+            written += 1;
+        }
+
+        writer.flush()?;
+
+        Ok(written)
+    }
+
+    // Write daily traffic of a single repository as CSV, oldest day first.
+    // Returns number of rows written.
+    pub fn export_repo_csv(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        writer: &mut dyn Write,
+    ) -> Result<usize, GithubStatsError> {
+        let mut stmt = self.conn.prepare(
+            r#"SELECT
+              date, v_count, v_uniq, c_count, c_uniq
+            FROM traffic
+            WHERE
+              owner=?1 AND repo=?2
+            ORDER BY date
+            "#,
+        )?;
+
+        writeln!(writer, "date,v_count,v_uniq,c_count,c_uniq")?;
+
+        let mut rows = stmt.query((owner, repo_name))?;
+        let mut written: usize = 0;
+
+        while let Some(row) = rows.next()? {
+            let date: String = row.get(0)?;
+            let (v_count, v_uniq, c_count, c_uniq): (u64, u64, u64, u64) = (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?);
+
+            writeln!(writer, "{},{},{},{},{}", date, v_count, v_uniq, c_count, c_uniq)?;
+            written += 1;
+        }
+
+        writer.flush()?;
+
+        Ok(written)
+    }
+
+    // Import daily traffic of a single repository from CSV written by export_repo_csv.
+    // Existing days are updated like with fetched traffic, future days are skipped.
+    // Everything is written in one transaction. Returns number of days written.
+    pub fn import_csv(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        reader: &mut dyn Read,
+    ) -> Result<usize, GithubStatsError> {
+        const HEADER: &str = "date,v_count,v_uniq,c_count,c_uniq";
+
+        let mut lines = BufReader::new(reader).lines();
+
+        match lines.next() {
+            Some(Ok(header)) if header.trim() == HEADER => {}
+            Some(Err(e)) => Err(e)?,
+            _ => Err(GithubStatsError::ParseError(format!("expected CSV header {}", HEADER)))?,
+        }
+
+        let mut views: Vec<DayStats> = Vec::new();
+        let mut clones: Vec<DayStats> = Vec::new();
+        let today = Utc::now().date_naive();
+
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // Header is line 1
+            let invalid = |what: &str| GithubStatsError::ParseError(format!("line {}: {}: {}", i + 2, what, line));
+
+            let fields: Vec<&str> = line.trim().split(',').collect();
+
+            if fields.len() != 5 {
+                Err(invalid("expected 5 fields"))?
+            }
+
+            let date = NaiveDate::parse_from_str(fields[0], "%Y-%m-%d")
+                .map_err(|_| invalid("invalid date"))?;
+
+            // update_traffic would skip it too, but then it would be counted as written
+            if date > today {
+                warn!("line {}: skipping {}, date is after current date {}", i + 2, date, today);
+                continue;
+            }
+
+            let timestamp = date.and_hms_opt(0, 0, 0).expect("midnight").and_utc();
+
+            let mut numbers: Vec<u64> = Vec::new();
+
+            for f in &fields[1..] {
+                numbers.push(f.parse().map_err(|_| invalid("invalid number"))?);
+            }
+
+            views.push(DayStats { timestamp, count: numbers[0], uniques: numbers[1] });
+            clones.push(DayStats { timestamp, count: numbers[2], uniques: numbers[3] });
+        }
+
+        let rows = views.len();
+
+        // Views aren't kept if clones can't be written
+        self.transaction(|| {
+            self.update_traffic(StatType::Views, owner, repo_name, views)?;
+            self.update_traffic(StatType::Clones, owner, repo_name, clones)
+        })?;
+
+        Ok(rows)
+    }
+
+    // Get views and clones summed over all repositories from given date onwards
+    pub fn total_traffic(
+        &self,
+        from: NaiveDate,
+    ) -> rusqlite::Result<(Stats, Stats)> {
+        self.conn.query_row(
+            r#"SELECT
+              COALESCE(SUM(v_count), 0), COALESCE(SUM(v_uniq), 0),
+              COALESCE(SUM(c_count), 0), COALESCE(SUM(c_uniq), 0)
+            FROM traffic
+            WHERE
+              date >= DATE(?1)
+          "#,
+            [from], |row| {
+                Ok((
+                    Stats {
+                        count: row.get(0)?,
+                        uniques: row.get(1)?,
+                    },
+                    Stats {
+                        count: row.get(2)?,
+                        uniques: row.get(3)?,
+                    },
+                ))
+            },
+        )
+    }
+
+    // Get views and clones of a single repository from given date onwards
+    pub fn repo_traffic(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        from: NaiveDate,
+    ) -> rusqlite::Result<(Stats, Stats)> {
+        self.conn.query_row(
+            r#"SELECT
+              COALESCE(SUM(v_count), 0), COALESCE(SUM(v_uniq), 0),
+              COALESCE(SUM(c_count), 0), COALESCE(SUM(c_uniq), 0)
+            FROM traffic
+            WHERE
+              owner=?1 AND repo=?2
+              AND date >= DATE(?3)
+          "#,
+            (owner, repo_name, from), |row| {
+                Ok((
+                    Stats {
+                        count: row.get(0)?,
+                        uniques: row.get(1)?,
+                    },
+                    Stats {
+                        count: row.get(2)?,
+                        uniques: row.get(3)?,
+                    },
+                ))
+            },
+        )
+    }
+
+    // Get views and clones of a single repository summed over all stored days
+    pub fn get_repo_totals(
+        &self,
+        owner: &str,
+        repo_name: &str,
+    ) -> rusqlite::Result<RepoTotals> {
+        self.conn.query_row(
+            r#"SELECT
+              COALESCE(SUM(v_count), 0), COALESCE(SUM(v_uniq), 0),
+              COALESCE(SUM(c_count), 0), COALESCE(SUM(c_uniq), 0)
+            FROM traffic
+            WHERE
+              owner=?1 AND repo=?2
+          "#,
+            (owner, repo_name), |row| {
+                Ok(RepoTotals {
+                    total_views: row.get(0)?,
+                    total_view_uniques: row.get(1)?,
+                    total_clones: row.get(2)?,
+                    total_clone_uniques: row.get(3)?,
+                })
+            },
+        )
+    }
+
+    // Remember when a repository was fetched
+    pub fn set_last_fetch(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        fetched_at: DateTime<Utc>,
+    ) -> rusqlite::Result<()> {
+        self.execute(
+            r#"INSERT OR REPLACE INTO
+                 fetch_log
+                 (owner, repo, fetched_at) VALUES
+                 (?1,    ?2,   ?3)
+                 "#,
+            (owner, repo_name, fetched_at),
+        )?;
+
+        Ok(())
+    }
+
+    // Remove all data of a repository, returns number of rows deleted from all tables.
+    // Owner and name must match exactly, they are compared with = and never as patterns.
+    pub fn delete_repo(
+        &self,
+        owner: &str,
+        repo_name: &str,
+    ) -> rusqlite::Result<usize> {
+        self.transaction(|| {
+            let mut deleted = 0;
+
+            for table in ["traffic", "referrers", "popular_paths", "stars", "fetch_log", "changed_repos"] {
+                deleted += self.execute(
+                    &format!("DELETE FROM {} WHERE owner=?1 AND repo=?2", table),
+                    (owner, repo_name),
+                )?;
+            }
+
+            Ok(deleted)
+        })
+    }
+
+    // Move history of a renamed repository from old_name to new_name of the same owner.
+    // Traffic of days stored under both names is summed into one row, snapshots and
+    // fetch times already stored under new_name are kept. Returns number of traffic rows
+    // moved or merged.
+    pub fn rename_repo(
+        &self,
+        owner: &str,
+        old_name: &str,
+        new_name: &str,
+    ) -> rusqlite::Result<usize> {
+        if old_name == new_name {
+            return Ok(0);
+        }
+
+        self.transaction(|| {
+            // Days with rows under both names: add old counts to new row.
+            // Rows can't be moved with UPDATE first, (y, m, d, owner, repo) would conflict.
+            // Correlated subquery instead of UPDATE ... FROM, which needs SQLite 3.33.
+            let merged = self.execute(
+                r#"UPDATE
+                     traffic
+                   SET
+                     (c_count, c_uniq, v_count, v_uniq) = (
+                       SELECT
+                         traffic.c_count + o.c_count,
+                         traffic.c_uniq + o.c_uniq,
+                         traffic.v_count + o.v_count,
+                         traffic.v_uniq + o.v_uniq
+                       FROM traffic AS o
+                       WHERE o.owner=?1 AND o.repo=?2
+                         AND o.y=traffic.y AND o.m=traffic.m AND o.d=traffic.d
+                     )
+                   WHERE
+                     owner=?1 AND repo=?3
+                     AND EXISTS (
+                       SELECT 1 FROM traffic AS o
+                       WHERE o.owner=?1 AND o.repo=?2
+                         AND o.y=traffic.y AND o.m=traffic.m AND o.d=traffic.d
+                     )
+                   "#,
+                (owner, old_name, new_name),
+            )?;
+
+            // Merged days are now only needed under new name
+            self.execute(
+                r#"DELETE FROM
+                     traffic
+                   WHERE
+                     owner=?1 AND repo=?2
+                     AND EXISTS (
+                       SELECT 1 FROM traffic AS t
+                       WHERE t.owner=?1 AND t.repo=?3
+                         AND t.y=traffic.y AND t.m=traffic.m AND t.d=traffic.d
+                     )
+                   "#,
+                (owner, old_name, new_name),
+            )?;
+
+            let moved = self.execute(
+                "UPDATE traffic SET repo=?3 WHERE owner=?1 AND repo=?2",
+                (owner, old_name, new_name),
+            )?;
+
+            // Snapshots aren't summed, rows conflicting with new name's own are dropped
+            for table in ["referrers", "popular_paths", "stars", "fetch_log", "changed_repos"] {
+                self.execute(
+                    &format!("UPDATE OR IGNORE {} SET repo=?3 WHERE owner=?1 AND repo=?2", table),
+                    (owner, old_name, new_name),
+                )?;
+
+                self.execute(
+                    &format!("DELETE FROM {} WHERE owner=?1 AND repo=?2", table),
+                    (owner, old_name),
+                )?;
+            }
+
+            Ok(merged + moved)
+        })
+    }
+
+    // Delete traffic rows, referrer and path snapshots and star counts dated before given date,
+    // from all repositories or one (owner, repo). Returns number of rows deleted, or which
+    // would be deleted with dry_run. Database file is vacuumed after deleting.
+    pub fn prune_old_data(
+        &self,
+        before: NaiveDate,
+        repo: Option<(&str, &str)>,
+        dry_run: bool,
+    ) -> rusqlite::Result<u64> {
+        let (owner, repo_name) = repo.unzip();
+        let mut count: u64 = 0;
+
+        for (table, date_col) in [
+            ("traffic", "date"),
+            ("referrers", "fetched_date"),
+            ("popular_paths", "fetched_date"),
+            ("stars", "date"),
+        ] {
+            let filter = format!("{} < ?1 AND (?2 IS NULL OR (owner=?2 AND repo=?3))", date_col);
+
+            if dry_run {
+                let n: u64 = self.conn.query_row(
+                    &format!("SELECT COUNT(*) FROM {} WHERE {}", table, filter),
+                    (before, owner, repo_name), |row| row.get(0),
+                )?;
+                count += n;
+            } else {
+                count += self.execute(
+                    &format!("DELETE FROM {} WHERE {}", table, filter),
+                    (before, owner, repo_name),
+                )? as u64;
+            }
+        }
+
+        if !dry_run && count > 0 {
+            self.conn.execute_batch("VACUUM")?;
+        }
+
+        Ok(count)
+    }
+
+    // Start a new set of changed repositories
+    pub fn clear_changed_repos(&self) -> rusqlite::Result<()> {
+        self.execute("DELETE FROM changed_repos", ())?;
+        Ok(())
+    }
+
+    // Add repository to set of changed repositories
+    pub fn mark_changed(
+        &self,
+        owner: &str,
+        repo_name: &str,
+    ) -> rusqlite::Result<()> {
+        self.execute(
+            r#"INSERT OR IGNORE INTO
+                 changed_repos
+                 (owner, repo) VALUES
+                 (?1,    ?2)
+                 "#,
+            (owner, repo_name),
+        )?;
+
+        Ok(())
+    }
+
+    // Repositories whose traffic changed during the latest fetch
+    pub fn changed_repos(&self) -> rusqlite::Result<Vec<Repo>> {
+        let mut stmt = self.conn.prepare(
+            r#"SELECT
+            owner, repo
+            FROM changed_repos
+            ORDER BY owner, repo
+            "#,
+        )?;
+
+        let mut res: Vec<Repo> = Vec::new();
+
+        let items = stmt.query_map(
+            [], |row| {
+                Ok(Repo {
+                    owner: row.get(0)?,
+                    name: row.get(1)?,
+                })
+            })?;
+
+        for item in items {
+            res.push(item?);
+        }
+
+        Ok(res)
+    }
+
+    // When a repository was last fetched, None if never
+    pub fn last_fetch(
+        &self,
+        owner: &str,
+        repo_name: &str,
+    ) -> rusqlite::Result<Option<DateTime<Utc>>> {
+        // Databases opened read-only might predate the table
+        let has_table: bool = self.conn.query_row(
+            r#"SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='fetch_log'"#,
+            [], |row| row.get(0),
+        )?;
+
+        if !has_table {
+            return Ok(None);
+        }
+
+        self.conn.query_row(
+            r#"SELECT
+              fetched_at
+            FROM fetch_log
+            WHERE
+              owner=?1 AND repo=?2
+          "#,
+            (owner, repo_name), |row| row.get(0),
+        ).optional()
+    }
+
+    // Get every date that has a traffic row for a repository, oldest first
+    pub fn stored_dates(
+        &self,
+        owner: &str,
+        repo_name: &str,
+    ) -> rusqlite::Result<Vec<NaiveDate>> {
+        let mut stmt = self.conn.prepare(
+            r#"SELECT DISTINCT
+              date
+            FROM traffic
+            WHERE
+              owner=?1 AND repo=?2
+            ORDER BY date
+            "#,
+        )?;
+
+        let mut res: Vec<NaiveDate> = Vec::new();
+
+        let items = stmt.query_map(
+            (owner, repo_name), |row| {
+                row.get(0)
+            })?;
+
+        for item in items {
+            res.push(item?);
+        }
+
+        Ok(res)
+    }
+
+    // First and last date with traffic of a repository, None if it has no traffic rows
+    pub fn get_date_range(
+        &self,
+        owner: &str,
+        repo_name: &str,
+    ) -> rusqlite::Result<Option<(NaiveDate, NaiveDate)>> {
+        let (first, last): (Option<NaiveDate>, Option<NaiveDate>) = self.conn.query_row(
+            r#"SELECT
+              MIN(date), MAX(date)
+            FROM traffic
+            WHERE
+              owner=?1 AND repo=?2
+          "#,
+            (owner, repo_name), |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        Ok(first.zip(last))
+    }
+
+    // Store snapshot of top referrers, replacing an earlier snapshot of the same day
+    pub fn update_referrers(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        fetched_date: NaiveDate,
+        referrers: &[ReferrerStats],
+    ) -> rusqlite::Result<usize> {
+        self.execute(
+            "DELETE FROM referrers WHERE fetched_date=?1 AND owner=?2 AND repo=?3",
+            (fetched_date, owner, repo_name),
+        )?;
+
+        let mut stored: usize = 0;
+
+        for r in referrers {
+            stored += self.execute(
+                r#"INSERT OR REPLACE INTO
+                     referrers
+                     (fetched_date, owner, repo, referrer, count, uniques) VALUES
+                     (?1,           ?2,    ?3,   ?4,       ?5,    ?6)
+                     "#,
+                (fetched_date, owner, repo_name, &r.referrer, r.count, r.uniques),
+            )?;
+        }
+
+        Ok(stored)
+    }
+
+    // Store snapshot of popular paths, replacing an earlier snapshot of the same day
+    pub fn update_popular_paths(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        fetched_date: NaiveDate,
+        paths: &[PopularPath],
+    ) -> rusqlite::Result<usize> {
+        self.execute(
+            "DELETE FROM popular_paths WHERE fetched_date=?1 AND owner=?2 AND repo=?3",
+            (fetched_date, owner, repo_name),
+        )?;
+
+        let mut stored: usize = 0;
+
+        for p in paths {
+            stored += self.execute(
+                r#"INSERT OR REPLACE INTO
+                     popular_paths
+                     (fetched_date, owner, repo, path, title, count, uniques) VALUES
+                     (?1,           ?2,    ?3,   ?4,   ?5,    ?6,    ?7)
+                     "#,
+                (fetched_date, owner, repo_name, &p.path, &p.title, p.count, p.uniques),
+            )?;
+        }
+
+        Ok(stored)
+    }
+
+    // Popular paths of the latest stored snapshot, most viewed first.
+    // Counts are for GitHub's 14 day window ending at the snapshot date.
+    pub fn get_popular_paths(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        limit: u32,
+    ) -> rusqlite::Result<Vec<PopularPath>> {
+        let mut stmt = self.conn.prepare(
+            r#"SELECT
+              path, title, count, uniques
+            FROM popular_paths
+            WHERE
+              owner=?1 AND repo=?2
+              AND fetched_date = (SELECT MAX(fetched_date) FROM popular_paths WHERE owner=?1 AND repo=?2)
+            ORDER BY count DESC, uniques DESC, path
+            LIMIT ?3
+            "#,
+        )?;
+
+        let mut res: Vec<PopularPath> = Vec::new();
+
+        let items = stmt.query_map(
+            (owner, repo_name, limit), |row| {
+                Ok(PopularPath {
+                    path: row.get(0)?,
+                    title: row.get(1)?,
+                    count: row.get(2)?,
+                    uniques: row.get(3)?,
+                })
+            })?;
+
+        for item in items {
+            res.push(item?);
+        }
+
+        Ok(res)
+    }
+
+    // Store star count of a day, replacing an earlier count of the same day
+    pub fn update_stars(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        date: NaiveDate,
+        count: u64,
+    ) -> rusqlite::Result<usize> {
+        self.execute(
+            r#"INSERT OR REPLACE INTO
+                 stars
+                 (date, owner, repo, count) VALUES
+                 (?1,   ?2,    ?3,   ?4)
+                 "#,
+            (date, owner, repo_name, count),
+        )
+    }
+
+    // Get top referrers summed over stored snapshots between two dates (inclusive)
+    pub fn top_referrers(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        from: NaiveDate,
+        to: NaiveDate,
+        limit: u32,
+    ) -> rusqlite::Result<Vec<ReferrerTotal>> {
+        let mut stmt = self.conn.prepare(
+            r#"SELECT
+              referrer,
+              SUM(count) count,
+              SUM(uniques) uniques
+            FROM referrers
+            WHERE
+              owner=?1 AND repo=?2
+              AND fetched_date >= DATE(?3) AND fetched_date <= DATE(?4)
+            GROUP BY referrer
+            ORDER BY count DESC, uniques DESC, referrer
+            LIMIT ?5
+            "#,
+        )?;
+
+        let mut res: Vec<ReferrerTotal> = Vec::new();
+
+        let items = stmt.query_map(
+            (owner, repo_name, from, to, limit), |row| {
+                Ok(ReferrerTotal {
+                    referrer: row.get(0)?,
+                    count: row.get(1)?,
+                    uniques: row.get(2)?,
+                })
+            })?;
+
+        for item in items {
+            res.push(item?);
+        }
+
+        Ok(res)
+    }
+
+    // Owners which have a repository with given name
+    pub fn repo_owners(
+        &self,
+        repo_name: &str,
+    ) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            r#"SELECT DISTINCT
+              owner
+            FROM traffic
+            WHERE
+              repo=?1
+            ORDER BY owner
+            "#,
+        )?;
+
+        let mut res: Vec<String> = Vec::new();
+
+        let items = stmt.query_map(
+            [repo_name], |row| {
+                row.get(0)
+            })?;
+
+        for item in items {
+            res.push(item?);
+        }
+
+        Ok(res)
+    }
+
     // Does given repository exist?
     pub fn repo_exists(
         &self,
@@ -221,3 +1321,92 @@ impl Database {
         Ok(false)
     }
 }
+
+// Cache key: owner, repo, reference date, days
+type StatsCacheKey = (String, String, NaiveDate, u32);
+
+// Database with a least recently used cache for get_repo_stats.
+// Other Database methods are available through Deref. Cached results are dropped
+// when the database's change marker moves, so writes can't leave stale results.
+pub struct CachedDatabase {
+    db: Database,
+    cache: RefCell<HashMap<StatsCacheKey, Vec<RepoStats>>>,
+    // Keys from least to most recently used
+    order: RefCell<VecDeque<StatsCacheKey>>,
+    // Database::change_marker when the cache was last checked
+    seen_changes: Cell<Option<(i64, i64)>>,
+}
+
+impl CachedDatabase {
+    // How many query results to keep
+    const CAPACITY: usize = 128;
+
+    // Get traffic stats of a single repository, cached
+    pub fn get_repo_stats(
+        &self,
+        owner: &str,
+        repo_name: &str,
+        now_ref: NaiveDate,
+        days: u32,
+    ) -> rusqlite::Result<Vec<RepoStats>> {
+        let marker = self.db.change_marker()?;
+
+        if self.seen_changes.get() != Some(marker) {
+            self.invalidate();
+            self.seen_changes.set(Some(marker));
+        }
+
+        let key: StatsCacheKey = (owner.to_string(), repo_name.to_string(), now_ref, days);
+
+        if let Some(stats) = self.cache.borrow().get(&key) {
+            self.touch(&key);
+            return Ok(stats.clone());
+        }
+
+        let stats = self.db.get_repo_stats(owner, repo_name, now_ref, days)?;
+
+        let mut cache = self.cache.borrow_mut();
+        let mut order = self.order.borrow_mut();
+
+        if cache.len() >= Self::CAPACITY {
+            // Evict least recently used
+            if let Some(oldest) = order.pop_front() {
+                cache.remove(&oldest);
+            }
+        }
+
+        cache.insert(key.clone(), stats.clone());
+        order.push_back(key);
+
+        Ok(stats)
+    }
+
+    // Get the wrapped database
+    pub fn into_inner(self) -> Database {
+        self.db
+    }
+
+    // Drop all cached query results
+    pub fn invalidate(&self) {
+        self.cache.borrow_mut().clear();
+        self.order.borrow_mut().clear();
+    }
+
+    // Mark key as most recently used
+    fn touch(&self, key: &StatsCacheKey) {
+        let mut order = self.order.borrow_mut();
+
+        if let Some(pos) = order.iter().position(|k| k == key) {
+            let k = order.remove(pos).unwrap();
+            order.push_back(k);
+        }
+    }
+}
+
+impl Deref for CachedDatabase {
+    type Target = Database;
+
+    fn deref(&self) -> &Self::Target {
+        &self.db
+    }
+}
@@ -1,24 +1,26 @@
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use reqwest::header::{HeaderMap, HeaderValue};
-use reqwest::{header, StatusCode};
-use reqwest::blocking::Client;
-use std::error::Error;
-use std::path::PathBuf;
-use std::{fs, thread};
-use std::fs::{metadata, remove_file};
+use reqwest::{header, Proxy, StatusCode};
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use std::path::{Path, PathBuf};
+use std::{fs, io, thread};
+use std::fs::remove_file;
 use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex};
 use regex::Regex;
 use serde::Deserialize;
 use chrono::{DateTime, Utc};
-use crate::StatType;
+use tracing::warn;
+use crate::{GithubStatsError, Repo, RetryPolicy, StatType};
 
 mod github_date_format {
-    use chrono::{DateTime, TimeZone, Utc};
+    use chrono::{DateTime, NaiveDateTime, Utc};
     use serde::{self, Deserialize, Deserializer, Serializer};
 
     // "2023-03-26T00:00:00Z" (UTC)
     const FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
 
+    #[allow(dead_code)]
     pub fn serialize<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
         where
             S: Serializer,
@@ -32,11 +34,18 @@ mod github_date_format {
             D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        Utc.datetime_from_str(&s, FORMAT)
+        NaiveDateTime::parse_from_str(&s, FORMAT)
+            .map(|dt| dt.and_utc())
             .map_err(serde::de::Error::custom)
     }
 }
 
+// Cache directory when not configured, relative to working directory
+pub const DEFAULT_CACHE_DIR: &str = "cache";
+
+// Traffic stats cache files under cache directory, see GithubStats::stats_cache_file
+pub const STATS_CACHE_SUBDIR: &str = "repos";
+
 // Github API
 #[derive(Deserialize)]
 pub struct DayStats {
@@ -58,12 +67,40 @@ pub struct CloningStats {
 // Github API
 // https://docs.github.com/en/rest/metrics/traffic?apiVersion=2022-11-28#get-page-views
 #[derive(Deserialize)]
-pub(crate) struct ViewStats {
+pub struct ViewStats {
     pub count: u64,
     pub uniques: u64,
     pub views: Vec<DayStats>,
 }
 
+// Github API
+// https://docs.github.com/en/rest/metrics/traffic?apiVersion=2022-11-28#get-top-referral-sources
+#[derive(Deserialize)]
+pub struct ReferrerStats {
+    pub referrer: String,
+    pub count: u64,
+    pub uniques: u64,
+}
+
+// Github API
+// https://docs.github.com/en/rest/metrics/traffic?apiVersion=2022-11-28#get-top-referral-paths
+#[derive(Deserialize)]
+pub struct PopularPath {
+    pub path: String,
+    pub title: String,
+    pub count: u64,
+    pub uniques: u64,
+}
+
+// Traffic stats for GitHub's 14 day window
+pub struct TrafficStats {
+    // Totals for the whole window
+    pub count: u64,
+    pub uniques: u64,
+    // Daily stats
+    pub days: Vec<DayStats>,
+}
+
 pub type GhRepo = Vec<GhRepoElement>;
 
 // Github API
@@ -200,25 +237,196 @@ pub struct Permissions {
     pub pull: bool,
 }
 
+// Repository list endpoints
+enum RepoListing {
+    // Repositories of a user
+    User(String),
+    // Repositories of an organization
+    Org(String),
+    // Repositories the authenticated user can access as an organization member, for example through teams
+    OrgMember,
+}
+
+impl RepoListing {
+    // URL for a single JSON page
+    fn url(&self, api_base: &str, per_page: u16, page_num: u64) -> String {
+        match self {
+            RepoListing::User(name) => format!(
+                "{}/users/{}/repos?type=all&sort=created&direction=asc&per_page={}&page={}",
+                api_base, name, per_page, page_num,
+            ),
+            RepoListing::Org(name) => format!(
+                "{}/orgs/{}/repos?type=all&sort=created&direction=asc&per_page={}&page={}",
+                api_base, name, per_page, page_num,
+            ),
+            RepoListing::OrgMember => format!(
+                "{}/user/repos?affiliation=organization_member&sort=created&direction=asc&per_page={}&page={}",
+                api_base, per_page, page_num,
+            ),
+        }
+    }
+
+    // Name for messages
+    fn name(&self) -> &str {
+        match self {
+            RepoListing::User(name) => name,
+            RepoListing::Org(name) => name,
+            RepoListing::OrgMember => "organization member repositories",
+        }
+    }
+
+    // Cache directory for JSON pages, kept apart from per-repository stats.
+    // GitHub names can't start with underscore so these can't collide.
+    fn cache_path(&self, cache_dir: &Path) -> PathBuf {
+        let lists = cache_dir.join("lists");
+
+        match self {
+            RepoListing::User(name) => lists.join(crate::path_component(name)),
+            RepoListing::Org(name) => lists.join("_orgs").join(crate::path_component(name)),
+            RepoListing::OrgMember => lists.join("_org_member"),
+        }
+    }
+}
+
+// Timing settings for GithubStats
+#[derive(Clone, Copy, Debug)]
+pub struct GithubStatsOptions {
+    // How long cached JSON files are used without asking GitHub.
+    // None uses cached files regardless of age, zero always asks GitHub.
+    pub cache_ttl: Option<Duration>,
+    // HTTP client's timeout
+    pub http_timeout: Duration,
+    // Sleep time between HTTP requests
+    // https://docs.github.com/en/rest/overview/resources-in-the-rest-api?apiVersion=2022-11-28#rate-limiting
+    pub rate_limit: Duration,
+}
+
+impl Default for GithubStatsOptions {
+    fn default() -> Self {
+        Self {
+            cache_ttl: Some(Duration::from_secs(60 * 60)),
+            http_timeout: Duration::from_secs(30),
+            rate_limit: Duration::from_millis(300),
+        }
+    }
+}
+
 // HTTP API client for GitHub
 #[derive(Clone)]
 pub struct GithubStats {
     http_client: Client,
+    // API deprecation warnings seen during this run
+    api_warnings: Arc<Mutex<Vec<String>>>,
+    // Use cache files only, regardless of age
+    offline: bool,
+    // Don't read cache files, they are still written
+    no_cache: bool,
+    // Cache TTL and delays
+    options: GithubStatsOptions,
+    // Directory for cached JSON files
+    cache_dir: PathBuf,
+    // REST API root URL without trailing slash
+    api_base: String,
+    // X-GitHub-Api-Version header
+    api_version: String,
+    // Accept header
+    accept: String,
+    // Retries for connection errors and server errors
+    retry: RetryPolicy,
+    // Bound for simultaneous requests, shared by clones
+    inflight: Arc<InflightLimit>,
+    // When the exhausted rate limit resets, as Unix time, shared by clones
+    rate_limit_reset: Arc<Mutex<Option<u64>>>,
+}
+
+// Counting semaphore for requests sent at the same time from multiple threads
+struct InflightLimit {
+    // Permits in use and maximum, None is unlimited
+    state: Mutex<(usize, Option<usize>)>,
+    released: Condvar,
+}
+
+impl InflightLimit {
+    fn new(max: Option<usize>) -> Self {
+        Self {
+            state: Mutex::new((0, max)),
+            released: Condvar::new(),
+        }
+    }
+
+    // Block until a request can be sent
+    fn acquire(&self) -> InflightPermit<'_> {
+        let mut state = self.state.lock().unwrap();
+
+        while state.1.map(|max| state.0 >= max).unwrap_or(false) {
+            state = self.released.wait(state).unwrap();
+        }
+
+        state.0 += 1;
+
+        InflightPermit { limit: self }
+    }
+}
+
+// Released when dropped
+struct InflightPermit<'a> {
+    limit: &'a InflightLimit,
+}
+
+impl Drop for InflightPermit<'_> {
+    fn drop(&mut self) {
+        self.limit.state.lock().unwrap().0 -= 1;
+        self.limit.released.notify_one();
+    }
 }
 
 impl GithubStats {
-    // Sleep time between HTTP requests
-    // https://docs.github.com/en/rest/overview/resources-in-the-rest-api?apiVersion=2022-11-28#rate-limiting
-    const RATE_LIMIT: Duration = Duration::from_millis(300);
+    // Wait for rate limit reset when fewer requests than this are remaining
+    const RATE_LIMIT_LOW: u64 = 2;
 
-    // HTTP client's timeout
-    const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+    // GitHub Enterprise Server uses https://{hostname}/api/v3
+    // https://docs.github.com/en/enterprise-server@latest/rest/quickstart
+    pub const DEFAULT_API_BASE: &'static str = "https://api.github.com";
+
+    // https://docs.github.com/en/rest/overview/api-versions
+    pub const DEFAULT_API_VERSION: &'static str = "2022-11-28";
 
-    // JSON file cache duration
-    const MAX_FILE_AGE: Duration = Duration::from_secs(60 * 60);
+    // https://docs.github.com/en/rest/overview/media-types
+    pub const DEFAULT_ACCEPT: &'static str = "application/vnd.github+json";
 
     pub fn new(
         api_key: &str, // GitHub API key
+        options: GithubStatsOptions,
+    ) -> Self {
+        Self::build(api_key, None, options)
+    }
+
+    // Client which connects through a proxy.
+    // http:// and https:// are HTTP proxies, socks5:// and socks5h:// are SOCKS5 proxies.
+    pub fn with_proxy(
+        api_key: &str, // GitHub API key
+        proxy_url: &str,
+        options: GithubStatsOptions,
+    ) -> Result<Self, GithubStatsError> {
+        let scheme = proxy_url
+            .split_once("://")
+            .map(|(scheme, _)| scheme.to_lowercase())
+            .unwrap_or_default();
+
+        let proxy = match scheme.as_str() {
+            // reqwest selects the protocol from the scheme,
+            // socks5h resolves host names on the proxy side
+            "http" | "https" | "socks5" | "socks5h" => Proxy::all(proxy_url)?,
+            _ => Err(GithubStatsError::InvalidInput(format!("unsupported proxy scheme in {}, use http://, https://, socks5:// or socks5h://", proxy_url)))?,
+        };
+
+        Ok(Self::build(api_key, Some(proxy), options))
+    }
+
+    fn build(
+        api_key: &str,
+        proxy: Option<Proxy>,
+        options: GithubStatsOptions,
     ) -> Self {
         let mut headers = HeaderMap::new();
 
@@ -226,18 +434,272 @@ impl GithubStats {
         let auth_value = HeaderValue::from_str(bearer.as_str()).expect("");
         headers.insert(header::AUTHORIZATION, auth_value);
 
-        headers.insert("Accept", header::HeaderValue::from_static("application/vnd.github+json"));
-        headers.insert("X-GitHub-Api-Version", header::HeaderValue::from_static("2022-11-28"));
-
-        let client = Client::builder()
+        let mut builder = Client::builder()
             .user_agent("Github stats")
             .default_headers(headers)
-            .timeout(Self::HTTP_TIMEOUT)
+            .timeout(options.http_timeout);
+
+        if let Some(p) = proxy {
+            builder = builder.proxy(p);
+        }
+
+        let client = builder
             .build()
             .unwrap();
 
         Self {
             http_client: client,
+            api_warnings: Default::default(),
+            offline: false,
+            no_cache: false,
+            options,
+            cache_dir: PathBuf::from(DEFAULT_CACHE_DIR),
+            api_base: Self::DEFAULT_API_BASE.to_string(),
+            api_version: Self::DEFAULT_API_VERSION.to_string(),
+            accept: Self::DEFAULT_ACCEPT.to_string(),
+            retry: RetryPolicy::default(),
+            inflight: Arc::new(InflightLimit::new(None)),
+            rate_limit_reset: Default::default(),
+        }
+    }
+
+    // Maximum number of requests sent at the same time by this client and its clones.
+    // Rate limit delay is per request before a request waits for its turn here,
+    // so with several threads this is what bounds the request rate.
+    pub fn set_max_inflight(&mut self, max: usize) {
+        self.inflight = Arc::new(InflightLimit::new(Some(max.max(1))));
+    }
+
+    // How to retry requests which failed for possibly temporary reasons
+    pub fn set_retry(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    // Use a different REST API root than DEFAULT_API_BASE, for example GitHub Enterprise Server
+    pub fn set_api_base(&mut self, api_base: &str) {
+        self.api_base = api_base.trim_end_matches('/').to_string();
+    }
+
+    // Request a different REST API version than DEFAULT_API_VERSION
+    pub fn set_api_version(&mut self, version: &str) {
+        self.api_version = version.to_string();
+    }
+
+    // Accept additional media types, for example preview types, besides DEFAULT_ACCEPT
+    pub fn add_accept(&mut self, media_type: &str) {
+        self.accept = format!("{}, {}", self.accept, media_type);
+    }
+
+    // GET request with API version and Accept headers.
+    // accept replaces the client's Accept header for this request only,
+    // for example application/vnd.github.star+json for starring timestamps.
+    fn request(&self, url: &str, accept: Option<&str>) -> RequestBuilder {
+        self.http_client
+            .get(url)
+            .header(header::ACCEPT, accept.unwrap_or(&self.accept))
+            .header("X-GitHub-Api-Version", &self.api_version)
+    }
+
+    // Send GET request, retrying connection errors, timeouts and 5xx responses.
+    // With if_none_match GitHub answers 304 Not Modified if the ETag still matches.
+    fn send(&self, url: &str, accept: Option<&str>, if_none_match: Option<&str>) -> reqwest::Result<Response> {
+        let mut retry = 0;
+        let mut waited_retry_after = false;
+
+        loop {
+            let last_try = retry + 1 >= self.retry.attempts;
+
+            self.wait_rate_limit(url);
+
+            // Permit is not held while sleeping between retries
+            let res = {
+                let _permit = self.inflight.acquire();
+                let mut request = self.request(url, accept);
+
+                if let Some(etag) = if_none_match {
+                    request = request.header(header::IF_NONE_MATCH, etag);
+                }
+
+                request.send()
+            };
+
+            if let Ok(r) = &res {
+                self.check_rate_limit(r.headers());
+            }
+
+            // Secondary rate limit, wait as long as GitHub asks and try once more
+            // https://docs.github.com/en/rest/overview/resources-in-the-rest-api#secondary-rate-limits
+            if let Ok(r) = &res {
+                if let Some(wait) = Self::retry_after(r) {
+                    if !waited_retry_after {
+                        warn!("secondary rate limit for {}, retrying in {:?}", url, wait);
+                        thread::sleep(wait);
+                        waited_retry_after = true;
+                        continue;
+                    }
+                }
+
+                // Primary rate limit ran out, next round waits for the reset
+                let exhausted = matches!(Self::rate_limit(r.headers()), Some((0, _)));
+
+                if exhausted && r.status() == StatusCode::FORBIDDEN && !waited_retry_after {
+                    waited_retry_after = true;
+                    continue;
+                }
+            }
+
+            let reason = match &res {
+                Ok(r) if r.status().is_server_error() => r.status().to_string(),
+                Err(e) if e.is_connect() || e.is_timeout() => e.to_string(),
+                _ => { return res; }
+            };
+
+            if last_try {
+                return res;
+            }
+
+            let delay = self.retry.delay(retry);
+            warn!("retrying {} in {:?}: {}", url, delay, reason);
+            thread::sleep(delay);
+            retry += 1;
+        }
+    }
+
+    // Retry-After of a 403 or 429 response, in seconds
+    fn retry_after(r: &Response) -> Option<Duration> {
+        if r.status() != StatusCode::FORBIDDEN && r.status() != StatusCode::TOO_MANY_REQUESTS {
+            return None;
+        }
+
+        let secs: u64 = r.headers()
+            .get(header::RETRY_AFTER)?
+            .to_str().ok()?
+            .trim()
+            .parse().ok()?;
+
+        Some(Duration::from_secs(secs))
+    }
+
+    // Error for an unexpected response status, RateLimited if GitHub still refuses
+    // because of rate limits after send has waited
+    fn status_error(r: &Response) -> GithubStatsError {
+        if let Some(wait) = Self::retry_after(r) {
+            return GithubStatsError::RateLimited { retry_after_secs: wait.as_secs() };
+        }
+
+        let refused = r.status() == StatusCode::FORBIDDEN || r.status() == StatusCode::TOO_MANY_REQUESTS;
+
+        if let (true, Some((0, reset))) = (refused, Self::rate_limit(r.headers())) {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            return GithubStatsError::RateLimited { retry_after_secs: reset.saturating_sub(now) };
+        }
+
+        GithubStatsError::HttpError(format!("status: {}", r.status()))
+    }
+
+    // Remaining requests and reset time as Unix time from X-RateLimit-* headers
+    // https://docs.github.com/en/rest/overview/resources-in-the-rest-api?apiVersion=2022-11-28#checking-the-status-of-your-rate-limit
+    fn rate_limit(headers: &HeaderMap) -> Option<(u64, u64)> {
+        let get = |name: &str| -> Option<u64> {
+            headers.get(name)?.to_str().ok()?.trim().parse().ok()
+        };
+
+        Some((get("x-ratelimit-remaining")?, get("x-ratelimit-reset")?))
+    }
+
+    // Remember reset time when rate limit is about to run out
+    fn check_rate_limit(&self, headers: &HeaderMap) {
+        if let Some((remaining, reset)) = Self::rate_limit(headers) {
+            if remaining < Self::RATE_LIMIT_LOW {
+                *self.rate_limit_reset.lock().unwrap() = Some(reset);
+            }
+        }
+    }
+
+    // Sleep until rate limit resets, if it ran out.
+    // Lock is held while sleeping so that other threads wait too.
+    fn wait_rate_limit(&self, url: &str) {
+        let mut reset = self.rate_limit_reset.lock().unwrap();
+
+        if let Some(at) = reset.take() {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+            if at >= now {
+                // Reset time has one second resolution
+                let wait = Duration::from_secs(at - now + 1);
+                warn!("rate limit exhausted, waiting {:?} for reset before {}", wait, url);
+                thread::sleep(wait);
+            }
+        }
+    }
+
+    // Use cached JSON files only, regardless of their age.
+    // Missing cache files are errors.
+    pub fn set_offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    // Always fetch from GitHub without reading cache files.
+    // Fetched JSON is still written to cache.
+    pub fn set_no_cache(&mut self, no_cache: bool) {
+        self.no_cache = no_cache;
+    }
+
+    // Directory for cached JSON files instead of DEFAULT_CACHE_DIR
+    pub fn set_cache_dir(&mut self, cache_dir: &Path) {
+        self.cache_dir = cache_dir.to_path_buf();
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    // Deprecation and sunset warnings received from GitHub API so far
+    pub fn api_warnings(&self) -> Vec<String> {
+        self.api_warnings.lock().unwrap().clone()
+    }
+
+    // Warn about endpoints GitHub has marked deprecated or to be removed
+    // https://docs.github.com/en/rest/overview/api-versions
+    fn check_deprecation(&self, url: &str, headers: &HeaderMap) {
+        let mut found: Vec<String> = Vec::new();
+
+        if let Some(hv) = headers.get("deprecation") {
+            warn!("GitHub API deprecation notice for {}: {:?}", url, hv);
+            found.push(format!("deprecated: {} ({:?})", url, hv));
+        }
+
+        if let Some(hv) = headers.get("sunset") {
+            // Sunset uses HTTP-date, for example "Sat, 31 Dec 2023 23:59:59 GMT"
+            let when = match hv.to_str().map(DateTime::parse_from_rfc2822) {
+                Ok(Ok(d)) => d.with_timezone(&Utc).to_string(),
+                _ => format!("{:?}", hv),
+            };
+
+            warn!("GitHub API endpoint {} will be removed on {}", url, when);
+            found.push(format!("removed on {}: {}", when, url));
+        }
+
+        if !found.is_empty() {
+            self.api_warnings.lock().unwrap().append(&mut found);
+        }
+    }
+
+    // Warn if token is missing a scope.
+    // Only classic tokens report scopes, so a missing header is not an error.
+    fn check_scope(&self, url: &str, headers: &HeaderMap, scopes: &[&str]) {
+        let hv = match headers.get("x-oauth-scopes") {
+            None => { return; }
+            Some(hv) => { hv.to_str().unwrap_or_default() }
+        };
+
+        let has_scope = hv
+            .split(',')
+            .map(|s| s.trim())
+            .any(|s| scopes.contains(&s));
+
+        if !has_scope {
+            warn!("GitHub API key is missing {} scope for {}, some repositories might not be listed", scopes[0], url);
         }
     }
 
@@ -245,13 +707,37 @@ impl GithubStats {
     pub fn get_repositories(
         &self,
         name: String,
-    ) -> Result<GhRepo, Box<dyn Error>> {
+    ) -> Result<GhRepo, GithubStatsError> {
+        self.get_listing(RepoListing::User(name))
+    }
+
+    // Get list of an organization's repositories
+    pub fn get_org_repositories(
+        &self,
+        org: String,
+    ) -> Result<GhRepo, GithubStatsError> {
+        self.get_listing(RepoListing::Org(org))
+    }
+
+    // Get list of repositories the API key's user can access as an organization member.
+    // Repositories granted through teams are only listed with read:org scope.
+    pub fn get_member_repositories(
+        &self,
+    ) -> Result<GhRepo, GithubStatsError> {
+        self.get_listing(RepoListing::OrgMember)
+    }
+
+    // Get all pages of a repository list
+    fn get_listing(
+        &self,
+        listing: RepoListing,
+    ) -> Result<GhRepo, GithubStatsError> {
         let mut l: GhRepo = GhRepo::new();
 
         let mut page_num = 1;
 
         loop {
-            let (mut repo, has_next) = self.get_repos(name.clone(), page_num)?;
+            let (mut repo, has_next) = self.get_repos(&listing, page_num)?;
             l.append(&mut repo);
 
             if !has_next {
@@ -267,84 +753,256 @@ impl GithubStats {
     // Get a single JSON page of repositories list
     fn get_repos(
         &self,
-        name: String, // Repository's name
+        listing: &RepoListing,
         page_num: u64,
-    ) -> Result<(GhRepo, bool), Box<dyn Error>> {
+    ) -> Result<(GhRepo, bool), GithubStatsError> {
         // How many repositories to list per JSON page
         const PER_PAGE: u16 = 100;
         let mut has_next = false;
 
-        let cache_path = PathBuf::from(format!("cache/repos/{}", name));
-        let mut json_repos_fname = PathBuf::from(cache_path.clone());
+        let cache_path = listing.cache_path(&self.cache_dir);
+        let mut json_repos_fname = cache_path.clone();
         json_repos_fname = json_repos_fname.join(format!("_REPOS_p{}.json", page_num));
 
-        fs::create_dir_all(cache_path)?;
+        fs::create_dir_all(&cache_path)
+            .map_err(|e| crate::with_context(e, format!("failed to create cache dir `{}`", cache_path.display())))?;
 
-        let mut repos_json: String = String::new();
+        let repos_json: String;
+        let mut from_cache = false;
+
+        // None if not cached, otherwise whether GitHub must be asked if it has changed
+        let expired = self.cache_expired(&json_repos_fname)?;
+
+        if expired == Some(false) {
+            repos_json = fs::read_to_string(&json_repos_fname)?;
+            from_cache = true;
+        } else {
+            if self.offline {
+                Err(GithubStatsError::NotFound(format!("offline: no cached repository list {}", json_repos_fname.display())))?
+            }
 
-        if !json_repos_fname.exists() {
             // Do not flood Github API
-            thread::sleep(Self::RATE_LIMIT);
-
-            repos_json = match self.http_client.get(
-                format!(
-                    "https://api.github.com/users/{}/repos?type=all&sort=created&direction=asc&per_page={}&page={}",
-                    name, PER_PAGE, page_num,
-                )
-            ).send() {
-                Ok(r) => {
-                    if r.status() == StatusCode::OK {
-                        match r.headers().get("link") {
-                            None => {}
-                            Some(hv) => {
-                                if !hv.is_empty() {
-                                    let raw = hv.to_str()?;
-                                    let link = Self::parse_links_header(raw);
-
-                                    if link.contains_key("next") {
-                                        // We have multiple pages of repos
-                                        has_next = true;
-                                    }
-                                }
+            thread::sleep(self.options.rate_limit);
+
+            let url = listing.url(&self.api_base, PER_PAGE, page_num);
+            let etag = expired.and_then(|_| Self::cached_etag(&json_repos_fname));
+
+            let r = self.send(&url, None, etag.as_deref())?;
+            self.check_deprecation(&url, r.headers());
+
+            if let RepoListing::OrgMember = listing {
+                if page_num == 1 {
+                    self.check_scope(&url, r.headers(), &["read:org", "write:org", "admin:org"]);
+                }
+            }
+
+            match r.status() {
+                StatusCode::NOT_MODIFIED if expired.is_some() => {
+                    // Unchanged, cached file is fresh again
+                    repos_json = fs::read_to_string(&json_repos_fname)?;
+                    Self::update_cache_file(&json_repos_fname, &repos_json, etag.as_deref())?;
+                    from_cache = true;
+                }
+                StatusCode::OK => {
+                    match r.headers().get("link") {
+                        None => {}
+                        Some(hv) if !hv.is_empty() => {
+                            let raw = hv.to_str()
+                                .map_err(|e| GithubStatsError::ParseError(format!("link header: {}", e)))?;
+                            let link = Self::parse_links_header(raw);
+
+                            if link.contains_key("next") {
+                                // We have multiple pages of repos
+                                has_next = true;
                             }
                         }
+                        Some(_) => {}
+                    }
 
-                        match r.text() {
-                            Ok(d) => { d }
-                            Err(e) => { Err(e.to_string())? }
-                        }
-                    } else {
-                        Err(format!("status: {}", r.status()))?
+                    let etag = Self::etag(r.headers());
+                    repos_json = r.text()?;
+
+                    if repos_json.is_empty() {
+                        Err(GithubStatsError::ParseError(format!("empty: {} (page {})", listing.name(), page_num)))?
                     }
-                }
-                Err(e) => { Err(e.to_string())? }
-            };
 
-            if repos_json.is_empty() {
-                Err(format!("empty: {} (page {})", name, page_num))?
+                    Self::update_cache_file(&json_repos_fname, &repos_json, etag.as_deref())?;
+                }
+                StatusCode::FORBIDDEN if matches!(listing, RepoListing::Org(_)) => {
+                    return match Self::status_error(&r) {
+                        GithubStatsError::HttpError(_) => Err(GithubStatsError::HttpError(format!(
+                            "status: {} listing repositories of organization {}, API key needs repo scope for private repositories",
+                            r.status(), listing.name(),
+                        ))),
+                        e => Err(e),
+                    };
+                }
+                _ => {
+                    return Err(Self::status_error(&r));
+                }
             }
+        }
 
-            crate::make_temp_file(json_repos_fname, repos_json.as_bytes())?;
+        let parsed = if repos_json.is_empty() {
+            Err(GithubStatsError::ParseError(format!("empty: {} (page {})", listing.name(), page_num)))
         } else {
-            let md = metadata(json_repos_fname.clone())?;
-            let file_age = md.created()?.elapsed()?;
+            serde_json::from_str::<GhRepo>(&repos_json).map_err(GithubStatsError::from)
+        };
+
+        if from_cache {
+            if let Ok(o) = &parsed {
+                // Link header isn't cached, a full page might have a next page
+                let next_fname = cache_path.join(format!("_REPOS_p{}.json", page_num + 1));
+                has_next = o.len() == PER_PAGE as usize && (!self.offline || next_fname.exists());
+            }
+        }
 
-            if file_age >= Self::MAX_FILE_AGE {
-                // Too old, fetch again
-                remove_file(json_repos_fname)?;
-                return self.get_repos(name, page_num);
+        match parsed {
+            Ok(o) => { Ok((o, has_next)) }
+            Err(e) if from_cache => {
+                // Broken cache file, for example a truncated write, fetch again
+                warn!("removing unreadable cache file {}: {}", json_repos_fname.display(), e);
+                remove_file(&json_repos_fname)?;
+                self.get_repos(listing, page_num)
             }
+            Err(e) => { Err(e) }
+        }
+    }
+
+    // Directory of per-repository cache files: {cache_dir}/repos
+    pub fn stats_cache_dir(cache_dir: &Path) -> PathBuf {
+        cache_dir.join(STATS_CACHE_SUBDIR)
+    }
+
+    // Cache file for a repository's traffic stats: {cache_dir}/repos/{owner}/{repo}/{kind}.json
+    // A directory per repository keeps for example repo "a_views" apart from views of repo "a".
+    pub fn stats_cache_file(
+        cache_dir: &Path,
+        owner: &str,
+        repo_name: &str,
+        stat_type: StatType,
+    ) -> PathBuf {
+        Self::repo_cache_file(cache_dir, owner, repo_name, stat_type.name())
+    }
+
+    // Cache file for other per-repository JSON: {cache_dir}/repos/{owner}/{repo}/{name}.json
+    fn repo_cache_file(
+        cache_dir: &Path,
+        owner: &str,
+        repo_name: &str,
+        name: &str,
+    ) -> PathBuf {
+        Self::stats_cache_dir(cache_dir)
+            .join(crate::path_component(owner))
+            .join(crate::path_component(repo_name))
+            .join(format!("{}.json", name))
+    }
+
+    // Get JSON from cache file, or from url if not cached or the file is too old.
+    // Returns JSON and whether it came from cache.
+    fn cached_json(
+        &self,
+        url: &str,
+        json_fname: &Path,
+        what: &str,
+    ) -> Result<(String, bool), GithubStatsError> {
+        let cache_path = json_fname.parent().expect("cache dir").to_path_buf();
+
+        fs::create_dir_all(&cache_path)
+            .map_err(|e| crate::with_context(e, format!("failed to create cache dir `{}`", cache_path.display())))?;
+
+        // None if not cached, otherwise whether GitHub must be asked if it has changed
+        let expired = self.cache_expired(json_fname)?;
 
-            repos_json = fs::read_to_string(json_repos_fname)?;
+        if expired == Some(false) {
+            return Ok((fs::read_to_string(json_fname)?, true));
         }
 
-        if repos_json.is_empty() {
-            Err(format!("empty: {} (page {})", name, page_num))?
+        if self.offline {
+            Err(GithubStatsError::NotFound(format!("offline: no cached {}", what)))?
         }
 
-        match serde_json::from_str::<GhRepo>(&repos_json) {
-            Ok(o) => { Ok((o, has_next)) }
-            Err(e) => { Err(e.to_string())? }
+        // Do not flood Github API
+        thread::sleep(self.options.rate_limit);
+
+        let etag = expired.and_then(|_| Self::cached_etag(json_fname));
+
+        let r = self.send(url, None, etag.as_deref())?;
+        self.check_deprecation(url, r.headers());
+
+        match r.status() {
+            StatusCode::NOT_MODIFIED if expired.is_some() => {
+                // Unchanged, cached file is fresh again
+                let json = fs::read_to_string(json_fname)?;
+                Self::update_cache_file(json_fname, &json, etag.as_deref())?;
+
+                Ok((json, true))
+            }
+            StatusCode::OK => {
+                let etag = Self::etag(r.headers());
+                let json = r.text()?;
+
+                if json.is_empty() {
+                    Err(GithubStatsError::ParseError(format!("empty: {}", what)))?
+                }
+
+                Self::update_cache_file(json_fname, &json, etag.as_deref())?;
+
+                Ok((json, false))
+            }
+            _ => {
+                Err(Self::status_error(&r))
+            }
+        }
+    }
+
+    // None if file isn't cached, Some(true) if it's too old to be used without checking
+    // for changes. In offline mode cached files never expire, with no_cache they are ignored.
+    fn cache_expired(&self, json_fname: &Path) -> io::Result<Option<bool>> {
+        if self.no_cache || !json_fname.exists() {
+            return Ok(None);
+        }
+
+        let file_age = crate::file_age(json_fname)?;
+
+        let expired = match self.options.cache_ttl {
+            None => false,
+            Some(ttl) => file_age >= ttl,
+        };
+
+        Ok(Some(!self.offline && expired))
+    }
+
+    // Sidecar file with the ETag of the response a cache file was written from
+    fn etag_file(json_fname: &Path) -> PathBuf {
+        let mut name = json_fname.as_os_str().to_os_string();
+        name.push(".etag");
+        PathBuf::from(name)
+    }
+
+    fn cached_etag(json_fname: &Path) -> Option<String> {
+        fs::read_to_string(Self::etag_file(json_fname))
+            .ok()
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    }
+
+    // ETag response header
+    fn etag(headers: &HeaderMap) -> Option<String> {
+        headers.get(header::ETAG)?.to_str().ok().map(|s| s.to_string())
+    }
+
+    // Write cache file and its ETag sidecar, a missing ETag removes an old sidecar.
+    // A new file is written even if only the ETag was confirmed, so that its modification time is updated.
+    fn update_cache_file(json_fname: &Path, json: &str, etag: Option<&str>) -> io::Result<()> {
+        crate::make_temp_file(json_fname.to_path_buf(), json.as_bytes())?;
+
+        let etag_fname = Self::etag_file(json_fname);
+
+        match etag {
+            Some(etag) => crate::make_temp_file(etag_fname, etag.as_bytes()),
+            None if etag_fname.exists() => remove_file(etag_fname),
+            None => Ok(()),
         }
     }
 
@@ -354,79 +1012,218 @@ impl GithubStats {
         stat_type: StatType,
         owner: &str,
         repo_name: &str,
-    ) -> Result<Vec<DayStats>, Box<dyn Error>> {
-        let n = match stat_type {
-            StatType::Clones => "clones",
-            StatType::Views => "views",
-        };
+    ) -> Result<TrafficStats, GithubStatsError> {
+        if stat_type == StatType::Referrers {
+            Err(GithubStatsError::InvalidInput(String::from("referrers have no daily stats, see get_referrers")))?
+        }
 
-        let cache_path = PathBuf::from(format!("cache/repos/{}", owner));
-        let mut json_stats_fname = PathBuf::from(cache_path.clone());
-        json_stats_fname = json_stats_fname.join(format!("{}_{}.json", repo_name, n));
+        let json_stats_fname = Self::stats_cache_file(&self.cache_dir, owner, repo_name, stat_type);
 
-        fs::create_dir_all(cache_path).expect("couldn't create cache directory");
+        let url = format!(
+            "{}/repos/{}/{}/traffic/{}?per=day",
+            self.api_base, owner, repo_name, stat_type.name()
+        );
 
-        let mut stats_json: String = String::new();
+        let what = format!("{} {}/{}", stat_type.name(), owner, repo_name);
+        let (stats_json, from_cache) = self.cached_json(&url, &json_stats_fname, &what)?;
 
-        if !json_stats_fname.exists() {
-            // Do not flood Github API
-            thread::sleep(Self::RATE_LIMIT);
-
-            stats_json = match self.http_client
-                .get(format!(
-                    "https://api.github.com/repos/{}/{}/traffic/{}?per=day",
-                    owner, repo_name, n
-                ))
-                .send()
-            {
-                Ok(r) => {
-                    if r.status() == StatusCode::OK {
-                        match r.text() {
-                            Ok(d) => d,
-                            Err(e) => { Err(e.to_string())? }
-                        }
-                    } else { Err(format!("status: {} ", r.status()))? }
-                }
-                Err(e) => { Err(e.to_string())? }
-            };
+        // Get totals and daily stats, if any
+        match Self::parse_stats(stat_type, &stats_json) {
+            Ok(o) => { Ok(o) }
+            Err(e) if from_cache => {
+                // Broken cache file, for example a truncated write, fetch again
+                warn!(repo = %format!("{}/{}", owner, repo_name), "removing unreadable cache file {}: {}", json_stats_fname.display(), e);
+                remove_file(&json_stats_fname)?;
+                self.get_stats(stat_type, owner, repo_name)
+            }
+            Err(e) => { Err(e)? }
+        }
+    }
+
+    // Get top 10 referrers of GitHub's 14 day window.
+    // An empty list means no referrer traffic.
+    pub fn get_referrers(
+        &self,
+        owner: &str,
+        repo_name: &str,
+    ) -> Result<Vec<ReferrerStats>, GithubStatsError> {
+        let json_fname = Self::stats_cache_file(&self.cache_dir, owner, repo_name, StatType::Referrers);
+
+        let url = format!(
+            "{}/repos/{}/{}/traffic/popular/referrers",
+            self.api_base, owner, repo_name
+        );
 
-            if stats_json.is_empty() {
-                Err(format!("empty: {} {}/{}", n, owner, repo_name))?
+        let what = format!("referrers {}/{}", owner, repo_name);
+        let (json, from_cache) = self.cached_json(&url, &json_fname, &what)?;
+
+        match serde_json::from_str::<Vec<ReferrerStats>>(&json) {
+            Ok(o) => { Ok(o) }
+            Err(e) if from_cache => {
+                // Broken cache file, for example a truncated write, fetch again
+                warn!(repo = %format!("{}/{}", owner, repo_name), "removing unreadable cache file {}: {}", json_fname.display(), e);
+                remove_file(&json_fname)?;
+                self.get_referrers(owner, repo_name)
             }
+            Err(e) => { Err(e)? }
+        }
+    }
 
-            crate::make_temp_file(json_stats_fname, stats_json.as_bytes())?;
-        } else {
-            let md = metadata(json_stats_fname.clone())?;
-            let file_age = md.created()?.elapsed()?;
+    // Get top 10 popular content paths of GitHub's 14 day window.
+    // An empty list means no views.
+    pub fn get_popular_paths(
+        &self,
+        owner: &str,
+        repo_name: &str,
+    ) -> Result<Vec<PopularPath>, GithubStatsError> {
+        let json_fname = Self::repo_cache_file(&self.cache_dir, owner, repo_name, "paths");
+
+        let url = format!(
+            "{}/repos/{}/{}/traffic/popular/paths",
+            self.api_base, owner, repo_name
+        );
+
+        let what = format!("popular paths {}/{}", owner, repo_name);
+        let (json, from_cache) = self.cached_json(&url, &json_fname, &what)?;
 
-            if file_age >= Self::MAX_FILE_AGE {
-                // Too old, fetch again
-                remove_file(json_stats_fname)?;
-                return self.get_stats(stat_type, owner, repo_name);
+        match serde_json::from_str::<Vec<PopularPath>>(&json) {
+            Ok(o) => { Ok(o) }
+            Err(e) if from_cache => {
+                // Broken cache file, for example a truncated write, fetch again
+                warn!(repo = %format!("{}/{}", owner, repo_name), "removing unreadable cache file {}: {}", json_fname.display(), e);
+                remove_file(&json_fname)?;
+                self.get_popular_paths(owner, repo_name)
             }
+            Err(e) => { Err(e)? }
+        }
+    }
 
-            stats_json = fs::read_to_string(json_stats_fname)?;
+    // GET JSON from a REST API path such as /repos/{owner}/{repo}/stargazers, with a different
+    // Accept header for this request only, for example application/vnd.github.star+json.
+    // Responses aren't cached.
+    pub fn get_json_with_accept(
+        &self,
+        path: &str,
+        accept: &str,
+    ) -> Result<String, GithubStatsError> {
+        if self.offline {
+            Err(GithubStatsError::NotFound(format!("offline: {} is never cached", path)))?
         }
 
-        if stats_json.is_empty() {
-            Err(format!("empty: {} {}/{}", n, owner, repo_name))?
+        // Do not flood Github API
+        thread::sleep(self.options.rate_limit);
+
+        let url = format!("{}{}", self.api_base, path);
+        let r = self.send(&url, Some(accept), None)?;
+        self.check_deprecation(&url, r.headers());
+
+        match r.status() {
+            StatusCode::OK => Ok(r.text()?),
+            _ => Err(Self::status_error(&r)),
         }
+    }
+
+    // Cache everything fetch reads of a repository, so that fetch can run offline later
+    pub fn prefetch(
+        &self,
+        owner: &str,
+        repo_name: &str,
+    ) -> Result<(), GithubStatsError> {
+        self.get_stats(StatType::Clones, owner, repo_name)?;
+        self.get_stats(StatType::Views, owner, repo_name)?;
+        self.get_referrers(owner, repo_name)?;
+        self.get_popular_paths(owner, repo_name)?;
 
-        // Get daily stats, if any
+        Ok(())
+    }
+
+    // Parse traffic API response or cache file
+    pub fn parse_stats(
+        stat_type: StatType,
+        json: &str,
+    ) -> Result<TrafficStats, GithubStatsError> {
         match stat_type {
             StatType::Clones => {
-                match serde_json::from_str::<CloningStats>(&stats_json) {
-                    Ok(o) => { Ok(o.clones) }
-                    Err(e) => { Err(e.to_string())? }
-                }
+                serde_json::from_str::<CloningStats>(json)
+                    .map(|o| TrafficStats {
+                        count: o.count,
+                        uniques: o.uniques,
+                        days: o.clones,
+                    })
+                    .map_err(GithubStatsError::from)
+            }
+            StatType::Referrers => {
+                Err(GithubStatsError::InvalidInput(String::from("referrers have no daily stats")))
             }
             StatType::Views => {
-                match serde_json::from_str::<ViewStats>(&stats_json) {
-                    Ok(o) => { Ok(o.views) }
-                    Err(e) => { Err(e.to_string())? }
+                serde_json::from_str::<ViewStats>(json)
+                    .map(|o| TrafficStats {
+                        count: o.count,
+                        uniques: o.uniques,
+                        days: o.views,
+                    })
+                    .map_err(GithubStatsError::from)
+            }
+        }
+    }
+
+    // Traffic stats files under cache dir, in both cache layouts:
+    // {owner}/{repo}/{kind}.json and the older {owner}/{repo}_{kind}.json
+    pub fn cached_stats_files(
+        cache_dir: &Path,
+    ) -> io::Result<Vec<(Repo, StatType, PathBuf)>> {
+        let mut res: Vec<(Repo, StatType, PathBuf)> = Vec::new();
+
+        for owner_entry in fs::read_dir(cache_dir)? {
+            let owner_path = owner_entry?.path();
+
+            if !owner_path.is_dir() {
+                continue;
+            }
+
+            let owner_dir = owner_path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+
+            for entry in fs::read_dir(&owner_path)? {
+                let path = entry?.path();
+                let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+
+                if path.is_dir() {
+                    // Current layout, names are encoded with path_component
+                    let (owner, name) = match (crate::parse_path_component(owner_dir), crate::parse_path_component(&file_name)) {
+                        (Some(o), Some(n)) => (o, n),
+                        _ => {
+                            warn!("skipping cache dir with invalid name {}", path.display());
+                            continue;
+                        }
+                    };
+
+                    for t in [StatType::Clones, StatType::Views] {
+                        let f = path.join(format!("{}.json", t.name()));
+
+                        if f.is_file() {
+                            res.push((Repo { owner: owner.clone(), name: name.clone() }, t, f));
+                        }
+                    }
+
+                    continue;
+                }
+
+                // Older layout, names as is. Repository lists were stored as _REPOS_p{N}.json.
+                if file_name.starts_with("_REPOS_") {
+                    continue;
+                }
+
+                for t in [StatType::Clones, StatType::Views] {
+                    if let Some(name) = file_name.strip_suffix(&format!("_{}.json", t.name())) {
+                        res.push((Repo { owner: owner_dir.to_string(), name: name.to_string() }, t, path.clone()));
+                    }
                 }
             }
         }
+
+        res.sort_by(|a, b| (a.0.full_name(), a.1.name()).cmp(&(b.0.full_name(), b.1.name())));
+
+        Ok(res)
     }
 
     // parse "Link" header
@@ -1,54 +1,354 @@
-use std::io;
-use std::fs::{File, metadata, remove_file, rename};
+use std::{fmt, fs, io};
+use std::error::Error;
+use std::fs::{File, remove_file, rename};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use chrono::NaiveDate;
+use chrono::{Days, NaiveDate};
 use rand::distributions::{Alphanumeric, DistString};
+use serde::Serialize;
 
 pub mod github;
 pub mod db;
 pub mod chart;
+pub mod sparkline;
 
 // Traffic types
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum StatType {
     Clones,
     Views,
+    // Top referrers, no daily stats
+    Referrers,
 }
 
-// Create a temporary file and move it to a target file
+impl StatType {
+    // Name used in API URLs and file names
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatType::Clones => "clones",
+            StatType::Views => "views",
+            StatType::Referrers => "referrers",
+        }
+    }
+}
+
+// First and last date, both inclusive, of a window of `days` days ending at now_ref.
+// A window always contains at least now_ref.
+pub fn date_range(now_ref: NaiveDate, days: u32) -> (NaiveDate, NaiveDate) {
+    let first = now_ref
+        .checked_sub_days(Days::new(days.saturating_sub(1) as u64))
+        .unwrap_or(NaiveDate::MIN);
+
+    (first, now_ref)
+}
+
+// How many times to try an operation which can fail temporarily,
+// for example an HTTP request or a write to a locked database
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    // Total tries, 1 means no retries
+    pub attempts: u32,
+    // Wait before the first retry, doubled for each following retry
+    pub base: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        // 3 retries after 1s, 2s and 4s, long enough for GitHub's transient 502s and 503s
+        Self {
+            attempts: 4,
+            base: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Wait before given retry, 0 being the first retry
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.base.saturating_mul(2u32.saturating_pow(retry))
+    }
+}
+
+// Make a name safe to use as a single path component.
+// Characters outside GitHub's name charset, and a leading dot, are percent-encoded
+// so that names like "..", "a/b" or "" can't escape or alias their parent directory.
+pub fn path_component(name: &str) -> String {
+    if name.is_empty() {
+        return String::from("%");
+    }
+
+    let mut res = String::new();
+
+    for (i, c) in name.chars().enumerate() {
+        let safe = c.is_ascii_alphanumeric() || c == '-' || c == '_' || (c == '.' && i > 0);
+
+        if safe {
+            res.push(c);
+        } else {
+            let mut buf = [0u8; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                res.push_str(&format!("%{:02X}", b));
+            }
+        }
+    }
+
+    res
+}
+
+// Name from a path component made with path_component, None if it isn't valid
+pub fn parse_path_component(s: &str) -> Option<String> {
+    if s == "%" {
+        return Some(String::new());
+    }
+
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut rest = s.as_bytes();
+
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+// Create a temporary file next to a target file and move it to the target
 fn make_temp_file(target: PathBuf, b: &[u8]) -> io::Result<()> {
     let random_str = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
 
-    let tmpname = PathBuf::from(
-        format!("cache/.tmp.{}.{}",
+    let tmpname = target.with_file_name(
+        format!(".tmp.{}.{}",
                 random_str,
                 target.extension().unwrap().to_str().expect("extension?")
         )
     );
 
-    let mut f = File::create(&tmpname)?;
+    let mut f = File::create(&tmpname)
+        .map_err(|e| with_context(e, format!("failed to create temp file `{}`", tmpname.display())))?;
     f.write_all(b)?;
     f.flush()?;
     drop(f);
 
-    rename(&tmpname, target)?;
+    move_file(&tmpname, &target)
+        .map_err(|e| with_context(e, format!("failed to move `{}` to `{}`", tmpname.display(), target.display())))?;
+
+    Ok(())
+}
+
+// Rename a file, or copy and remove it if it's on a different filesystem than the target.
+// Only rename replaces the target atomically, so temporary files should be created
+// next to the target and copying is a fallback for mount points and bind mounts.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            remove_file(from)
+        }
+        res => res,
+    }
+}
+
+// Remove per-repository cache files of one repository given as owner/repo, or of all repositories.
+// Returns number of files removed, or which would be removed with dry_run.
+pub fn clear_cache(cache_dir: &Path, repo: Option<&str>, dry_run: bool) -> io::Result<usize> {
+    let stats_dir = github::GithubStats::stats_cache_dir(cache_dir);
+
+    let dir = match repo {
+        None => stats_dir,
+        Some(r) => {
+            let (owner, name) = r.split_once('/')
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("expected owner/repo, got {}", r)))?;
+
+            stats_dir.join(path_component(owner)).join(path_component(name))
+        }
+    };
+
+    if !dir.exists() {
+        return Ok(0);
+    }
+
+    let count = count_files(&dir)?;
+
+    if !dry_run {
+        fs::remove_dir_all(&dir)
+            .map_err(|e| with_context(e, format!("failed to remove `{}`", dir.display())))?;
+    }
+
+    Ok(count)
+}
+
+// Number of files in a directory tree
+fn count_files(dir: &Path) -> io::Result<usize> {
+    let mut count = 0;
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_dir() {
+            count += count_files(&entry.path())?;
+        } else {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+// Time since a file was last modified.
+// Modification time is used instead of creation time, which isn't available on all
+// platforms and filesystems. Cache files are always replaced by rename in make_temp_file,
+// so their modification time is when they were written.
+// File from the future, for example after a clock change, has zero age.
+pub fn file_age(path: &Path) -> io::Result<Duration> {
+    Ok(fs::metadata(path)?.modified()?.elapsed().unwrap_or_default())
+}
+
+// Add context to an I/O error, keeping its kind
+pub fn with_context(e: io::Error, context: String) -> io::Error {
+    io::Error::new(e.kind(), format!("{}: {}", context, e))
+}
+
+// Create a directory if it's missing and check that files can be written to it
+pub fn ensure_writable_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+        .map_err(|e| with_context(e, format!("failed to create directory `{}`", dir.display())))?;
+
+    let random_str = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+    let probe = dir.join(format!(".write-test.{}", random_str));
+
+    File::create(&probe)
+        .map_err(|e| with_context(e, format!("directory `{}` is not writable", dir.display())))?;
+    remove_file(&probe)?;
 
     Ok(())
 }
 
+// Errors of library functions
+#[derive(Debug)]
+pub enum GithubStatsError {
+    // Missing repository, cache file or other item
+    NotFound(String),
+    DatabaseError(rusqlite::Error),
+    // Failed request or unexpected response status from GitHub API
+    HttpError(String),
+    IoError(io::Error),
+    // Invalid or empty JSON, or invalid response header
+    ParseError(String),
+    // Invalid argument or configuration value
+    InvalidInput(String),
+    // Chart couldn't be rendered
+    ChartError(String),
+    // GitHub API rate limit still exceeded after waiting for it
+    RateLimited { retry_after_secs: u64 },
+}
+
+impl fmt::Display for GithubStatsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GithubStatsError::NotFound(s) => write!(f, "{}", s),
+            GithubStatsError::DatabaseError(e) => write!(f, "database error: {}", e),
+            GithubStatsError::HttpError(s) => write!(f, "HTTP error: {}", s),
+            GithubStatsError::IoError(e) => write!(f, "{}", e),
+            GithubStatsError::ParseError(s) => write!(f, "parse error: {}", s),
+            GithubStatsError::InvalidInput(s) => write!(f, "{}", s),
+            GithubStatsError::ChartError(s) => write!(f, "chart error: {}", s),
+            GithubStatsError::RateLimited { retry_after_secs } => write!(f, "GitHub API rate limit exceeded, retry after {}s", retry_after_secs),
+        }
+    }
+}
+
+impl Error for GithubStatsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GithubStatsError::DatabaseError(e) => Some(e),
+            GithubStatsError::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for GithubStatsError {
+    fn from(e: rusqlite::Error) -> Self {
+        GithubStatsError::DatabaseError(e)
+    }
+}
+
+impl From<io::Error> for GithubStatsError {
+    fn from(e: io::Error) -> Self {
+        GithubStatsError::IoError(e)
+    }
+}
+
+impl From<reqwest::Error> for GithubStatsError {
+    fn from(e: reqwest::Error) -> Self {
+        GithubStatsError::HttpError(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for GithubStatsError {
+    fn from(e: serde_json::Error) -> Self {
+        GithubStatsError::ParseError(e.to_string())
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Repo {
     pub owner: String,
     pub name: String,
 }
 
+impl Repo {
+    // "owner/name"
+    pub fn full_name(&self) -> String {
+        format!("{}/{}", self.owner, self.name)
+    }
+}
+
+#[derive(Clone, Serialize)]
 pub struct Stats {
     pub count: u64,
     pub uniques: u64,
 }
 
+// Serialized as {"date": "2023-10-01", "views": {"count", "uniques"}, "clones": {...}}
+#[derive(Clone, Serialize)]
 pub struct RepoStats {
     pub date: NaiveDate,
     pub views: Stats,
     pub clones: Stats,
 }
+
+impl RepoStats {
+    // Daily stats of a traffic type, None for referrers
+    pub fn get(&self, stat_type: StatType) -> Option<&Stats> {
+        match stat_type {
+            StatType::Clones => Some(&self.clones),
+            StatType::Views => Some(&self.views),
+            StatType::Referrers => None,
+        }
+    }
+}
+
+// Traffic of a repository summed over all stored days
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RepoTotals {
+    pub total_views: u64,
+    pub total_view_uniques: u64,
+    pub total_clones: u64,
+    pub total_clone_uniques: u64,
+}
+
+// Referrer traffic summed over a date range
+pub struct ReferrerTotal {
+    pub referrer: String,
+    pub count: u64,
+    pub uniques: u64,
+}
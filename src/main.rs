@@ -1,34 +1,344 @@
 use std::{fs, io};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::rename;
+use std::fs::File;
+use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::process::exit;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use chrono::{NaiveDate, Utc};
-use clap::{Args, command, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use rand::distributions::{Alphanumeric, DistString};
 use serde::Deserialize;
 use toml::from_str;
-
-use githubstats::chart::ChartGenerator;
-use githubstats::db::Database;
-use githubstats::github::{GhRepo, GithubStats};
+use tracing::{debug, warn, Level};
+
+use githubstats::chart::{ChartOptions, ChartType, OutputFormat as ImageFormat, Theme, chart_from_stats, embedded_hash, parse_color, parse_utc_offset, valid_date_format};
+use githubstats::db::{CachedDatabase, Database, SCHEMA_VERSION};
+use githubstats::github::{DayStats, GhRepo, GithubStats, GithubStatsOptions, PopularPath, ReferrerStats, DEFAULT_CACHE_DIR, TrafficStats};
+use githubstats::sparkline::{daily_values, sparkline};
+use githubstats::{clear_cache, date_range, ensure_writable_dir, with_context, move_file, path_component, GithubStatsError, Repo, RetryPolicy, Stats};
+use githubstats::StatType;
 use githubstats::StatType::{Clones, Views};
 
+// Fallback for --days
+const DEFAULT_DAYS: u32 = 30;
+
+// Exit status when fetch stopped because --deadline was reached
+const EXIT_DEADLINE: i32 = 3;
+
+// Upper bound for update --fetch-jobs, GitHub limits concurrent requests
+const MAX_FETCH_JOBS: i64 = 8;
+
+// Chart directory when --output-dir isn't given
+const DEFAULT_OUTPUT_DIR: &str = "stats";
+
 // Config file
 #[derive(Deserialize)]
 struct Config {
     // keys [database], [github], etc...
     database: ConfigDatabase,
     github: ConfigGitHub,
+    #[serde(default)]
+    defaults: ConfigDefaults,
+    #[serde(default)]
+    chart: ConfigChart,
+    #[serde(default)]
+    retry: ConfigRetry,
+    #[serde(default)]
+    cache: ConfigCache,
+    fetch: Option<ConfigFetch>,
+    // Display names for charts, "owner/repo" = "My Cool Library"
+    #[serde(default)]
+    titles: HashMap<String, String>,
+    #[serde(default)]
+    annotations: Vec<ConfigAnnotation>,
+}
+
+impl Config {
+    // Chart options with command line overrides and display names
+    fn chart_config(&self, args: &ChartArgs) -> ConfigChart {
+        let mut chart_cfg = self.chart.merge(args);
+        chart_cfg.titles = self.titles.clone();
+
+        // Config annotations first, then --annotate
+        chart_cfg.annotations = self.annotations
+            .iter()
+            .map(|a| (a.date, a.label.clone()))
+            .chain(args.annotate.iter().cloned())
+            .collect();
+
+        chart_cfg
+    }
+
+    // Replace ${VAR} references with environment variables in values which commonly differ between hosts
+    fn expand_env(&mut self) -> Result<(), String> {
+        self.github.apikey = expand_env_vars(&self.github.apikey)?;
+        self.github.user = expand_env_vars(&self.github.user)?;
+
+        if let Some(org) = &self.github.org {
+            self.github.org = Some(expand_env_vars(org)?);
+        }
+
+        if let Some(proxy) = &self.github.proxy {
+            self.github.proxy = Some(expand_env_vars(proxy)?);
+        }
+
+        let filename = self.database.filename.to_str()
+            .ok_or("database file name is not valid UTF-8")?;
+        self.database.filename = PathBuf::from(expand_env_vars(filename)?);
+
+        if let Some(dir) = &self.cache.dir {
+            let dir = dir.to_str()
+                .ok_or("cache directory name is not valid UTF-8")?;
+            self.cache.dir = Some(PathBuf::from(expand_env_vars(dir)?));
+        }
+
+        Ok(())
+    }
+}
+
+// Expand ${VAR} references, unset variables are errors
+fn expand_env_vars(s: &str) -> Result<String, String> {
+    let mut res = String::new();
+    let mut rest = s;
+
+    while let Some(start) = rest.find("${") {
+        res.push_str(&rest[..start]);
+
+        let end = rest[start..].find('}')
+            .ok_or(format!("unterminated variable reference in {}", s))?;
+
+        let name = &rest[start + 2..start + end];
+        let value = std::env::var(name)
+            .map_err(|e| format!("environment variable {}: {}", name, e))?;
+
+        res.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+
+    res.push_str(rest);
+
+    Ok(res)
+}
+
+// Config file key: [retry]
+#[derive(Deserialize, Default)]
+struct ConfigRetry {
+    attempts: Option<u32>, // Total tries for HTTP requests and busy database writes
+    base_ms: Option<u64>, // Wait before first retry, doubled for each retry
+}
+
+impl ConfigRetry {
+    fn policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::default();
+
+        RetryPolicy {
+            attempts: self.attempts.unwrap_or(default.attempts).max(1),
+            base: self.base_ms.map(Duration::from_millis).unwrap_or(default.base),
+        }
+    }
+}
+
+// Config file key: [cache]
+#[derive(Deserialize, Default)]
+struct ConfigCache {
+    dir: Option<PathBuf>, // Directory for cached GitHub API responses (default "cache")
+    ttl_minutes: Option<i64>, // 0 uses cache regardless of age, negative always fetches. Can't be set with [fetch] cache_ttl_seconds.
+}
+
+impl ConfigCache {
+    // Cache TTL from ttl_minutes, None if not set
+    fn ttl(&self) -> Option<Option<Duration>> {
+        self.ttl_minutes.map(|m| match m {
+            0 => None,
+            m if m < 0 => Some(Duration::ZERO),
+            m => Some(Duration::from_secs(m as u64 * 60)),
+        })
+    }
+
+    fn dir(&self) -> PathBuf {
+        self.dir.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE_DIR))
+    }
+}
+
+// Config file key: [fetch]
+#[derive(Deserialize, Default)]
+struct ConfigFetch {
+    cache_ttl_seconds: Option<u64>, // How long cached API responses are used (default 3600), 0 uses cache regardless of age
+    http_timeout_seconds: Option<u64>, // Timeout of a GitHub API request (default 30)
+    rate_limit_ms: Option<u64>, // Wait before each GitHub API request (default 300)
+    retry_count: Option<u32>, // Retries for GitHub API requests, overrides [retry] attempts
+}
+
+impl ConfigFetch {
+    fn options(&self) -> GithubStatsOptions {
+        let default = GithubStatsOptions::default();
+
+        GithubStatsOptions {
+            // 0 means the same as [cache] ttl_minutes = 0 and --cache-ttl 0
+            cache_ttl: match self.cache_ttl_seconds {
+                None => default.cache_ttl,
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+            },
+            http_timeout: self.http_timeout_seconds.map(Duration::from_secs).unwrap_or(default.http_timeout),
+            rate_limit: self.rate_limit_ms.map(Duration::from_millis).unwrap_or(default.rate_limit),
+        }
+    }
+}
+
+// Config file key: [chart]
+#[derive(Deserialize, Default, Clone, Debug)]
+struct ConfigChart {
+    width: Option<u32>,
+    height: Option<u32>,
+    font: Option<String>, // Font family
+    colors: Option<Vec<String>>, // Series colors as "#rrggbb"
+    legend_average: Option<bool>, // Show average per day in legend
+    timezone: Option<String>, // Timezone for displayed dates as UTC offset, "+03:00"
+    date_format: Option<String>, // strftime format for displayed dates
+    theme: Option<Theme>, // "light" or "dark"
+    chart_type: Option<ChartStyle>, // "points", "bar" or "line"
+    format: Option<ChartFormat>, // "svg" or "png"
+    flat_layout: Option<bool>, // Write stats/{repo}_{kind}.svg instead of stats/{owner}/{repo}_{kind}.svg
+    title_filenames: Option<bool>, // Use display names from [titles] in file names
+    #[serde(skip)]
+    annotations: Vec<(NaiveDate, String)>, // From [[annotations]] and command line
+    #[serde(skip)]
+    titles: HashMap<String, String>, // From [titles]
+    #[serde(skip)]
+    force: bool, // From command line
+}
+
+impl ConfigChart {
+    // Validated chart options for a repository
+    fn chart_options(&self, owner: &str, repo_name: &str) -> Result<ChartOptions, GithubStatsError> {
+        let mut opts = ChartOptions::default();
+
+        // Series colors from config
+        for c in self.colors.iter().flatten() {
+            match parse_color(c) {
+                Some(color) => opts.colors.push(color),
+                None => {
+                    Err(GithubStatsError::InvalidInput(format!("invalid chart color in config: {}", c)))?
+                }
+            }
+        }
+
+        if let Some(tz) = &self.timezone {
+            match parse_utc_offset(tz) {
+                Some(o) => opts.utc_offset = Some(o),
+                None => Err(GithubStatsError::InvalidInput(format!("invalid timezone {}, use UTC offset like +03:00", tz)))?,
+            }
+        }
+
+        if let Some(date_format) = &self.date_format {
+            if !valid_date_format(date_format) {
+                Err(GithubStatsError::InvalidInput(format!("invalid date format {}", date_format)))?
+            }
+
+            opts.date_format = date_format.clone();
+        }
+
+        opts.width = self.width.unwrap_or(opts.width);
+        opts.height = self.height.unwrap_or(opts.height);
+        opts.font = self.font.clone();
+        opts.legend_average = self.legend_average.unwrap_or(false);
+        opts.annotations = self.annotations.clone();
+        opts.theme = self.theme.unwrap_or_default();
+        opts.chart_type = self.chart_type.map(|t| t.chart_type()).unwrap_or_default();
+        opts.output_format = self.format.map(|f| f.image_format()).unwrap_or_default();
+        opts.title = self.titles.get(&format!("{}/{}", owner, repo_name)).cloned();
+
+        Ok(opts)
+    }
+
+    // Command line arguments override config
+    fn merge(&self, args: &ChartArgs) -> Self {
+        Self {
+            width: args.width.or(self.width),
+            height: args.height.or(self.height),
+            font: args.font.clone().or(self.font.clone()),
+            colors: self.colors.clone(),
+            legend_average: args.legend_average.or(self.legend_average),
+            timezone: args.timezone.clone().or(self.timezone.clone()),
+            date_format: args.date_format.clone().or(self.date_format.clone()),
+            theme: args.theme.or(self.theme),
+            chart_type: args.chart_type.or(self.chart_type),
+            format: args.format.or(self.format),
+            flat_layout: self.flat_layout,
+            title_filenames: self.title_filenames,
+            annotations: args.annotate.clone(),
+            titles: self.titles.clone(),
+            force: args.force,
+        }
+    }
+}
+
+// Config file key: [[annotations]]
+#[derive(Deserialize)]
+struct ConfigAnnotation {
+    date: NaiveDate, // YYYY-MM-DD
+    label: String,
+}
+
+// Config file key: [defaults]
+#[derive(Deserialize, Default)]
+struct ConfigDefaults {
+    days: Option<u32>, // Days used when --days is not given
 }
 
 // Config file key: [github]
 #[derive(Deserialize)]
 struct ConfigGitHub {
     apikey: String,
+    // User or organization whose repositories are fetched, only one can be set
+    #[serde(default)]
     user: String,
+    org: Option<String>,
+    // "org" if user is an organization, same as setting org instead of user
+    #[serde(default)]
+    account_type: AccountType,
+    // Also fetch repositories accessible as organization member (requires read:org scope)
+    #[serde(default)]
+    org_member_repos: bool,
+    // Proxy URL: http://host:port or socks5://host:port
+    proxy: Option<String>,
+    // REST API root, default https://api.github.com, GitHub Enterprise Server: https://{hostname}/api/v3
+    api_base: Option<String>,
+    // X-GitHub-Api-Version header, default 2022-11-28
+    api_version: Option<String>,
+    // Additional Accept media types, for example preview types
+    #[serde(default)]
+    accept: Vec<String>,
+}
+
+// Config file key: [github] account_type
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+enum AccountType {
+    #[default]
+    User,
+    Org,
+}
+
+impl ConfigGitHub {
+    // Move user to org for account_type = "org"
+    fn resolve_account_type(&mut self) -> Result<(), String> {
+        if self.account_type == AccountType::Org && !self.user.is_empty() {
+            if self.org.is_some() {
+                return Err(String::from("account_type = \"org\" uses user as organization name, org can't be set too"));
+            }
+
+            self.org = Some(std::mem::take(&mut self.user));
+        }
+
+        Ok(())
+    }
 }
 
 // Config file key: [database]
@@ -54,17 +364,113 @@ struct CLIArgs {
     #[arg(global = true)]
     config: PathBuf,
 
+    #[clap(long, value_enum, default_value = "text",
+    help = "Log format")]
+    #[arg(global = true)]
+    log_format: LogFormat,
+
+    #[clap(long, allow_negative_numbers = true,
+    help = "Minutes cached GitHub API responses are used, 0 uses cache regardless of age, -1 always fetches [default: from config or 60]")]
+    #[arg(global = true)]
+    cache_ttl: Option<i64>,
+
+    #[clap(long, default_value = "false", conflicts_with = "cache_ttl",
+    help = "Always fetch from GitHub without reading cached responses, they are still written to cache")]
+    #[arg(global = true)]
+    no_cache: bool,
+
     #[command(subcommand)]
     #[clap(help = "Command")]
     command: Commands,
 }
 
+// Log output formats
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogFormat {
+    Text,
+    // One JSON object per line, for log aggregators
+    Json,
+}
+
+// Chart selection on command line
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ChartKind {
+    Clones,
+    Views,
+}
+
+impl ChartKind {
+    fn stat_type(&self) -> StatType {
+        match self {
+            ChartKind::Clones => Clones,
+            ChartKind::Views => Views,
+        }
+    }
+}
+
+// Chart type on command line and in config
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+enum ChartStyle {
+    Points,
+    Bar,
+    Line,
+}
+
+impl ChartStyle {
+    fn chart_type(&self) -> ChartType {
+        match self {
+            ChartStyle::Points => ChartType::Points,
+            ChartStyle::Bar => ChartType::Bars,
+            ChartStyle::Line => ChartType::Line,
+        }
+    }
+}
+
+// Chart image format on command line and in config
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+enum ChartFormat {
+    Svg,
+    Png,
+}
+
+impl ChartFormat {
+    fn image_format(&self) -> ImageFormat {
+        match self {
+            ChartFormat::Svg => ImageFormat::Svg,
+            ChartFormat::Png => ImageFormat::Png,
+        }
+    }
+}
+
+// Output formats for commands printing rows
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    // Aligned columns
+    Table,
+    Csv,
+    // Array of objects, column names as keys
+    Json,
+    Markdown,
+}
+
+impl OutputFormat {
+    // Formats meant for other programs, which must not be mixed with other output
+    fn is_machine_readable(&self) -> bool {
+        matches!(self, OutputFormat::Csv | OutputFormat::Json)
+    }
+}
+
 // CLI subcommands
 #[derive(Subcommand, Debug)]
 enum Commands {
     #[clap(about = "Fetch traffic statistics from Github to a local database")]
     Fetch(CommandFetchArgs),
 
+    #[clap(about = "Fetch traffic statistics from Github to cache only, without updating database")]
+    Prefetch(CommandPrefetchArgs),
+
     #[clap(about = "List repositories found in local database")]
     ListRepos(CommandListReposArgs),
 
@@ -73,36 +479,445 @@ enum Commands {
 
     #[clap(about = "Generate all statistics from local database")]
     Generate(CommandGenerateArgs),
+
+    #[clap(about = "Fetch traffic statistics and generate all statistics in one run")]
+    Update(CommandUpdateArgs),
+
+    #[clap(about = "Print a traffic report of all repositories from local database")]
+    Report(CommandReportArgs),
+
+    #[clap(about = "Print a one line traffic summary of a repository")]
+    Status(CommandStatusArgs),
+
+    #[clap(about = "Print traffic totals of a repository")]
+    Totals(CommandTotalsArgs),
+
+    #[clap(about = "Export daily traffic of a repository from local database as JSON")]
+    Export(CommandExportArgs),
+
+    #[clap(about = "Export daily traffic of all repositories or one repository from local database as CSV")]
+    ExportCsv(CommandExportCsvArgs),
+
+    #[clap(about = "Import daily traffic of a repository from CSV written by export-csv --repo")]
+    ImportCsv(CommandImportCsvArgs),
+
+    #[clap(about = "Print most viewed content paths of a repository from latest fetch")]
+    Paths(CommandPathsArgs),
+
+    #[clap(about = "Run a read-only SQL query (SELECT or PRAGMA) against local database")]
+    Query(CommandQueryArgs),
+
+    #[clap(about = "Import traffic statistics from cache files to local database, without network access")]
+    Reindex,
+
+    #[clap(about = "Check local database for corruption")]
+    CheckDb,
+
+    #[clap(about = "Back up local database and replace it with an empty one")]
+    ResetDb(CommandResetDbArgs),
+
+    #[clap(about = "Remove cached GitHub API responses of all repositories or one repository")]
+    ClearCache(CommandClearCacheArgs),
+
+    #[clap(visible_alias = "forget",
+    about = "Remove a repository's data from local database and cache, for example after it was deleted on GitHub")]
+    DeleteRepo(CommandDeleteRepoArgs),
+
+    #[clap(about = "Merge history stored under a repository's old name into its new name")]
+    RenameRepo(CommandRenameRepoArgs),
+
+    #[clap(about = "Delete data older than a date from local database")]
+    Prune(CommandPruneArgs),
+}
+
+#[derive(Args, Debug)]
+struct CommandFetchArgs {
+    #[clap(long, value_enum,
+    help = "Don't store possibly partial first and/or last day of GitHub's 14 day window")]
+    drop_partial: Option<PartialDays>,
+
+    #[clap(long, default_value = "false",
+    help = "Use cached data only, see prefetch")]
+    offline: bool,
+
+    #[clap(long, default_value = "false",
+    help = "If repository list can't be fetched, fetch repositories already in local database")]
+    db_repos_fallback: bool,
+
+    #[clap(long, value_parser = humantime::parse_duration,
+    help = "Stop after the repository being fetched when running longer than this, for example 10m")]
+    deadline: Option<Duration>,
+
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..),
+    help = "Maximum number of simultaneous GitHub API requests [default: unlimited]")]
+    max_inflight: Option<u16>,
+
+    #[clap(short = 'r', long, value_parser = parse_full_repo, conflicts_with = "db_repos_fallback",
+    help = "Fetch only this repository, given as owner/repo, without fetching repository list")]
+    repo: Option<Repo>,
 }
 
 #[derive(Args, Debug)]
-struct CommandFetchArgs {}
+struct CommandPrefetchArgs {
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..),
+    help = "Maximum number of simultaneous GitHub API requests [default: unlimited]")]
+    max_inflight: Option<u16>,
+}
+
+// Which edge(s) of GitHub's traffic window to drop
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PartialDays {
+    First,
+    Last,
+    Both,
+}
 
 #[derive(Args, Debug)]
-struct CommandListReposArgs {}
+struct CommandListReposArgs {
+    #[clap(long, default_value = "false",
+    help = "Print only repository rows, without summary header and footer")]
+    plain: bool,
+
+    #[clap(short = 'f', long, value_enum,
+    help = "Output format, csv and json imply --plain")]
+    format: Option<OutputFormat>,
+
+    #[clap(long, default_value = "false",
+    help = "Show views and clones summed over all stored days")]
+    totals: bool,
+}
 
 #[derive(Args, Debug)]
 struct CommandStatsArgs {
-    #[clap(short = 'd', long, default_value = "30",
-    help = "Days")]
-    days: u32,
+    #[clap(short = 'd', long,
+    help = "Days [default: from config or 30]")]
+    days: Option<u32>,
+
+    #[command(flatten)]
+    chart: ChartArgs,
+
+    #[clap(short = 's', long, default_value = "false",
+    help = "Print a sparkline to terminal instead of generating SVG")]
+    sparkline: bool,
+
+    #[clap(short = 'k', long, value_enum,
+    help = "Generate only this chart [default: both]")]
+    kind: Option<ChartKind>,
+
+    // No short flag, -o is --output-dir like in generate
+    #[clap(long, requires = "kind", conflicts_with = "sparkline",
+    help = "Write chart to this file instead of stats directory, - for standard output")]
+    output: Option<PathBuf>,
+
+    #[clap(short = 'o', long, default_value = DEFAULT_OUTPUT_DIR, conflicts_with = "output",
+    help = "Directory for charts")]
+    output_dir: PathBuf,
 
     #[clap(required = true,
-    help = "Repository")]
+    help = "Repository as owner/repo, or repo if only one owner has that name")]
     repo: String,
 }
 
 #[derive(Args, Debug)]
 struct CommandGenerateArgs {
-    #[clap(short = 'd', long, default_value = "30",
+    #[clap(short = 'd', long,
+    help = "Days [default: from config or 30]")]
+    days: Option<u32>,
+
+    #[clap(long, default_value = "false",
+    help = "Only repositories whose traffic changed in latest fetch")]
+    changed_only: bool,
+
+    #[clap(short = 'o', long, default_value = DEFAULT_OUTPUT_DIR,
+    help = "Directory for charts")]
+    output_dir: PathBuf,
+
+    #[command(flatten)]
+    chart: ChartArgs,
+}
+
+#[derive(Args, Debug)]
+struct CommandUpdateArgs {
+    #[clap(short = 'd', long,
+    help = "Days [default: from config or 30]")]
+    days: Option<u32>,
+
+    #[clap(long, value_enum,
+    help = "Don't store possibly partial first and/or last day of GitHub's 14 day window")]
+    drop_partial: Option<PartialDays>,
+
+    #[clap(long, default_value = "2", value_parser = clap::value_parser!(u16).range(1..=MAX_FETCH_JOBS),
+    help = "Repositories fetched from GitHub at the same time")]
+    fetch_jobs: u16,
+
+    #[clap(long, value_parser = clap::value_parser!(u16).range(1..),
+    help = "Charts rendered at the same time, at most number of CPUs [default: number of CPUs]")]
+    render_jobs: Option<u16>,
+
+    #[command(flatten)]
+    chart: ChartArgs,
+}
+
+#[derive(Args, Debug)]
+struct CommandReportArgs {
+    #[clap(long, required = true,
+    help = "Last 7 days compared to 7 days before")]
+    weekly: bool,
+}
+
+#[derive(Args, Debug)]
+struct CommandStatusArgs {
+    #[clap(short = 'd', long, default_value = "7",
     help = "Days")]
     days: u32,
+
+    #[clap(short = 'r', long, required = true,
+    help = "Repository as owner/repo, or repo if only one owner has that name")]
+    repo: String,
+}
+
+#[derive(Args, Debug)]
+struct CommandTotalsArgs {
+    #[clap(short = 'd', long,
+    help = "Days [default: all stored days]")]
+    days: Option<u32>,
+
+    #[clap(short = 'r', long, required = true,
+    help = "Repository as owner/repo, or repo if only one owner has that name")]
+    repo: String,
+
+    #[clap(long, default_value = "false",
+    help = "Print as shell variable assignments, for example GHSTATS_VIEWS=320")]
+    shell: bool,
+
+    #[clap(short = 'f', long, value_enum, conflicts_with = "shell",
+    help = "Print as one row in this format instead of name: value lines")]
+    format: Option<OutputFormat>,
+}
+
+#[derive(Args, Debug)]
+struct CommandExportArgs {
+    #[clap(short = 'd', long,
+    help = "Days [default: from config or 30]")]
+    days: Option<u32>,
+
+    #[clap(short = 'o', long,
+    help = "Write to this file instead of standard output")]
+    output: Option<PathBuf>,
+
+    #[clap(short = 'f', long, value_enum, default_value = "json",
+    help = "Output format, json keeps views and clones as nested objects")]
+    format: OutputFormat,
+
+    #[clap(required = true,
+    help = "Repository as owner/repo, or repo if only one owner has that name")]
+    repo: String,
+}
+
+#[derive(Args, Debug)]
+struct CommandExportCsvArgs {
+    #[clap(long, value_parser = parse_date,
+    help = "First day to export, YYYY-MM-DD [default: oldest stored day]")]
+    from: Option<NaiveDate>,
+
+    #[clap(long, value_parser = parse_date,
+    help = "Last day to export, YYYY-MM-DD [default: newest stored day]")]
+    to: Option<NaiveDate>,
+
+    #[clap(short = 'o', long,
+    help = "Output file, - for standard output [default: {repo}_stats.csv with --repo, otherwise standard output]")]
+    output: Option<PathBuf>,
+
+    #[clap(short = 'r', long, conflicts_with_all = ["from", "to"],
+    help = "Export only this repository, as owner/repo or repo if only one owner has that name")]
+    repo: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct CommandImportCsvArgs {
+    #[clap(short = 'r', long, required = true, value_parser = parse_full_repo,
+    help = "Repository as owner/repo")]
+    repo: Repo,
+
+    #[clap(short = 'f', long, required = true,
+    help = "CSV file, - for standard input")]
+    file: PathBuf,
+}
+
+#[derive(Args, Debug)]
+struct CommandPathsArgs {
+    #[clap(short = 'r', long, required = true,
+    help = "Repository as owner/repo, or repo if only one owner has that name")]
+    repo: String,
+
+    #[clap(short = 'n', long, default_value = "10",
+    help = "Number of paths")]
+    limit: u32,
+
+    #[clap(short = 'f', long, value_enum, default_value = "table",
+    help = "Output format")]
+    format: OutputFormat,
+}
+
+#[derive(Args, Debug)]
+struct CommandQueryArgs {
+    #[clap(long, default_value = "false", conflicts_with = "format",
+    help = "Deprecated, use --format csv")]
+    csv: bool,
+
+    #[clap(short = 'f', long, value_enum, default_value = "table",
+    help = "Output format")]
+    format: OutputFormat,
+
+    #[clap(required = true,
+    help = "SQL statement")]
+    sql: String,
+}
+
+#[derive(Args, Debug)]
+struct CommandResetDbArgs {
+    #[clap(long, default_value = "false",
+    help = "Confirm reset")]
+    yes: bool,
+}
+
+#[derive(Args, Debug)]
+struct CommandClearCacheArgs {
+    #[clap(short = 'r', long,
+    help = "Repository as owner/repo [default: all repositories]")]
+    repo: Option<String>,
+
+    #[clap(long, default_value = "false",
+    help = "Only print how many files would be removed")]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+struct CommandPruneArgs {
+    #[clap(long, required = true, value_parser = parse_date,
+    help = "Delete data dated before this day, YYYY-MM-DD")]
+    before: NaiveDate,
+
+    #[clap(short = 'r', long,
+    help = "Repository as owner/repo, or repo if only one owner has that name [default: all repositories]")]
+    repo: Option<String>,
+
+    #[clap(long, default_value = "false",
+    help = "Only print how many rows would be deleted")]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+struct CommandDeleteRepoArgs {
+    #[clap(long, default_value = "false",
+    help = "Confirm deletion")]
+    confirm: bool,
+
+    #[clap(required = true, value_parser = parse_full_repo,
+    help = "Repository as owner/repo, must match exactly")]
+    repo: Repo,
+}
+
+#[derive(Args, Debug)]
+struct CommandRenameRepoArgs {
+    #[clap(required = true, value_parser = parse_full_repo,
+    help = "Old name as owner/repo, must match exactly")]
+    repo: Repo,
+
+    #[clap(required = true,
+    help = "New name of the repository, same owner")]
+    new_name: String,
+}
+
+// Chart options, override [chart] in config
+#[derive(Args, Debug)]
+struct ChartArgs {
+    #[clap(long,
+    help = "Chart width in pixels [default: from config or 640]")]
+    width: Option<u32>,
+
+    #[clap(long,
+    help = "Chart height in pixels [default: from config or 480]")]
+    height: Option<u32>,
+
+    #[clap(long,
+    help = "Chart font family [default: from config or sans-serif]")]
+    font: Option<String>,
+
+    #[clap(long,
+    help = "Show average per day in legend [default: from config or false]")]
+    legend_average: Option<bool>,
+
+    #[clap(long, allow_hyphen_values = true,
+    help = "Timezone for displayed dates as UTC offset, for example +03:00 [default: from config or UTC]")]
+    timezone: Option<String>,
+
+    #[clap(long,
+    help = "strftime format for displayed dates [default: from config or %Y-%m-%d]")]
+    date_format: Option<String>,
+
+    #[clap(long, value_enum,
+    help = "Chart colors [default: from config or light]")]
+    theme: Option<Theme>,
+
+    #[clap(long, value_enum,
+    help = "Draw daily values as points, bars or a line [default: from config or points]")]
+    chart_type: Option<ChartStyle>,
+
+    #[clap(long, value_enum,
+    help = "Chart image format, png needs the png feature [default: from config or svg]")]
+    format: Option<ChartFormat>,
+
+    #[clap(long, value_parser = parse_annotation,
+    help = "Mark a date with a vertical line and label, for example 2023-04-01=v1.0 (repeatable)")]
+    annotate: Vec<(NaiveDate, String)>,
+
+    #[clap(long, default_value = "false",
+    help = "Render and write charts even if existing file was rendered from the same data")]
+    force: bool,
+}
+
+// Parse owner/repo
+fn parse_full_repo(s: &str) -> Result<Repo, String> {
+    match s.split_once('/') {
+        Some((owner, name)) if !owner.is_empty() && !name.is_empty() && !name.contains('/') => Ok(Repo {
+            owner: owner.to_string(),
+            name: name.to_string(),
+        }),
+        _ => Err(format!("expected owner/repo, got {}", s)),
+    }
+}
+
+// Parse YYYY-MM-DD
+fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|e| format!("invalid date {}: {}", s, e))
+}
+
+// Parse DATE=LABEL
+fn parse_annotation(s: &str) -> Result<(NaiveDate, String), String> {
+    let (date, label) = s.split_once('=')
+        .ok_or(format!("expected DATE=LABEL, got {}", s))?;
+
+    let date = parse_date(date)?;
+
+    Ok((date, label.to_string()))
 }
 
 
 fn main() -> Result<(), io::Error> {
     let args: CLIArgs = CLIArgs::parse();
 
+    // Warnings are always shown, verbose adds debug output
+    let log = tracing_subscriber::fmt()
+        .with_max_level(if args.verbose { Level::DEBUG } else { Level::WARN })
+        .with_writer(io::stderr);
+
+    match args.log_format {
+        LogFormat::Text => log.init(),
+        LogFormat::Json => log.json().flatten_event(true).init(),
+    }
+
     if !args.config.exists() {
         eprintln!("couldn't find config file");
         exit(1)
@@ -116,7 +931,7 @@ fn main() -> Result<(), io::Error> {
     let contents = fs::read_to_string(args.config)
         .expect("couldn't read config file");
 
-    let config = match from_str::<Config>(&contents) {
+    let mut config = match from_str::<Config>(&contents) {
         Ok(cfg) => { cfg }
         Err(e) => {
             eprintln!("config file error: {}", e);
@@ -125,46 +940,133 @@ fn main() -> Result<(), io::Error> {
     };
     drop(contents);
 
+    if let Err(e) = config.expand_env() {
+        eprintln!("config file error: {}", e);
+        exit(1)
+    }
+
+    if let Err(e) = config.github.resolve_account_type() {
+        eprintln!("config file error: {}", e);
+        exit(1)
+    }
+
+    // Two keys for the same setting, neither can silently win
+    if config.cache.ttl_minutes.is_some() && config.fetch.as_ref().is_some_and(|f| f.cache_ttl_seconds.is_some()) {
+        eprintln!("config file error: set only one of [cache] ttl_minutes and [fetch] cache_ttl_seconds");
+        exit(1)
+    }
+
+    // Command line overrides config
+    if args.cache_ttl.is_some() {
+        config.cache.ttl_minutes = args.cache_ttl;
+    }
+
+    if let Err(e) = Database::validate_path(&config.database.filename) {
+        eprintln!("config file error: {}", e);
+        exit(1)
+    }
+
     // Get a static reference to current date so that
     // if user's stats generation might slip into next day
     // the generated date range remains the same
     let now_reference = Utc::now().date_naive();
 
-    let db = Database::new(&config.database.filename);
+    // Days to use when not given on command line
+    let default_days = config.defaults.days.unwrap_or(DEFAULT_DAYS);
 
-    match args.command {
-        Commands::Fetch(_) => {
-            if config.github.user.is_empty() {
-                eprintln!("no GitHub user in config file");
-                exit(1)
-            }
+    let retry = config.retry.policy();
 
-            if config.github.apikey.is_empty() {
-                eprintln!("no GitHub API key in config file");
+    // Database is opened only by commands which use it
+    let open_db = || {
+        let mut db = match Database::new(&config.database.filename) {
+            Ok(d) => { d }
+            Err(e) => {
+                eprintln!("error opening database {}: {}", config.database.filename.display(), e);
                 exit(1)
             }
+        };
+        db.set_retry(retry);
+        db.with_stats_cache()
+    };
 
-            let ghsc = GithubStats::new(&config.github.apikey);
-
-            println!("Fetching repository list for https://github.com/{} ..", config.github.user);
-            let repos: GhRepo = match ghsc.get_repositories(config.github.user) {
-                Ok(r) => { r }
+    match args.command {
+        Commands::Fetch(fetchargs) => {
+            let mut ghsc = match github_client(&config, fetchargs.offline, args.no_cache) {
+                Ok(g) => { g }
                 Err(e) => {
                     eprintln!("{}", e);
-                    exit(1);
+                    exit(1)
                 }
             };
 
-            if repos.is_empty() {
-                println!("No repositories found");
-                exit(0)
+            if let Some(n) = fetchargs.max_inflight {
+                ghsc.set_max_inflight(n as usize);
             }
 
-            for repo in repos {
-                println!("Repo https://github.com/{} :", repo.full_name);
+            let db = open_db();
+            let started = Instant::now();
 
-                // --- Clone stats
-                let clone_stats = match ghsc.get_stats(Clones, &repo.owner.login, &repo.name) {
+            let repo_list = match fetchargs.repo {
+                Some(repo) => Ok(vec![(repo, None)]),
+                None => fetch_repo_list(&ghsc, &config.github),
+            };
+
+            let repos = match repo_list {
+                Ok(r) => { r }
+                Err(e) if fetchargs.db_repos_fallback => {
+                    eprintln!("{}", e);
+                    eprintln!("Warning: couldn't get repository list from GitHub, using repositories in local database. New repositories are missing.");
+
+                    match db.get_repo_list() {
+                        Ok(r) => { r.into_iter().map(|repo| (repo, None)).collect() }
+                        Err(e) => {
+                            eprintln!("error getting repo list: {}", e);
+                            exit(1)
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            };
+
+            if repos.is_empty() {
+                println!("No repositories found");
+                exit(0)
+            }
+
+            let repos = match store_stars(&db, repos) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("error saving stars: {}", e);
+                    exit(1)
+                }
+            };
+
+            // Changed set is per run, see generate --changed-only
+            if let Err(e) = db.clear_changed_repos() {
+                eprintln!("error clearing changed repositories: {}", e);
+                exit(1)
+            }
+            let repo_count = repos.len();
+
+            for (processed, repo) in repos.into_iter().enumerate() {
+                // Checked between repositories so that a repository is never left half updated
+                if let Some(deadline) = fetchargs.deadline {
+                    if started.elapsed() >= deadline {
+                        println!("Deadline of {} reached, {} of {} repositories processed.",
+                                 humantime::format_duration(deadline), processed, repo_count);
+                        print_api_warnings(&ghsc);
+                        exit(EXIT_DEADLINE)
+                    }
+                }
+
+                println!("Repo https://github.com/{} :", repo.full_name());
+                debug!(repo = %repo.full_name(), "fetching traffic");
+
+                // --- Clone stats
+                let clone_stats = match ghsc.get_stats(Clones, &repo.owner, &repo.name) {
                     Ok(d) => { d }
                     Err(e) => {
                         eprintln!("error traffic clones: {}", e);
@@ -172,13 +1074,8 @@ fn main() -> Result<(), io::Error> {
                     }
                 };
 
-                if !clone_stats.is_empty() {
-                    println!("  Updating clones...");
-                    db.update_traffic(Clones, &repo.owner.login, &repo.name, clone_stats);
-                }
-
                 // --- View stats
-                let view_stats = match ghsc.get_stats(Views, &repo.owner.login, &repo.name) {
+                let view_stats = match ghsc.get_stats(Views, &repo.owner, &repo.name) {
                     Ok(d) => { d }
                     Err(e) => {
                         eprintln!("error traffic views: {}", e);
@@ -186,22 +1083,82 @@ fn main() -> Result<(), io::Error> {
                     }
                 };
 
-                if !view_stats.is_empty() {
-                    println!("  Updating views...");
-                    db.update_traffic(Views, &repo.owner.login, &repo.name, view_stats);
+                // --- Top referrers
+                let referrers = match ghsc.get_referrers(&repo.owner, &repo.name) {
+                    Ok(d) => { d }
+                    Err(e) => {
+                        eprintln!("error traffic referrers: {}", e);
+                        exit(1)
+                    }
+                };
+
+                // --- Popular paths
+                let paths = match ghsc.get_popular_paths(&repo.owner, &repo.name) {
+                    Ok(d) => { d }
+                    Err(e) => {
+                        eprintln!("error traffic popular paths: {}", e);
+                        exit(1)
+                    }
+                };
+
+                if let Err(e) = store_traffic(&db, &repo, clone_stats, view_stats, &referrers, &paths, fetchargs.drop_partial) {
+                    eprintln!("error saving traffic of {}: {}", repo.full_name(), e);
+                    exit(1)
                 }
             }
 
             println!("Database file {} updated.", config.database.filename.display());
+
+            print_api_warnings(&ghsc);
+        }
+
+        // Fetch to cache only
+        Commands::Prefetch(prefetchargs) => {
+            let mut ghsc = match github_client(&config, false, args.no_cache) {
+                Ok(g) => { g }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            };
+
+            if let Some(n) = prefetchargs.max_inflight {
+                ghsc.set_max_inflight(n as usize);
+            }
+
+            let repos = match fetch_repo_list(&ghsc, &config.github) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            };
+
+            if repos.is_empty() {
+                println!("No repositories found");
+                exit(0)
+            }
+
+            for (repo, _) in repos {
+                if let Err(e) = ghsc.prefetch(&repo.owner, &repo.name) {
+                    eprintln!("error prefetching {}: {}", repo.full_name(), e);
+                    exit(1)
+                }
+
+                println!("Cached https://github.com/{}", repo.full_name());
+            }
+
+            print_api_warnings(&ghsc);
         }
 
         // List repos found in database
-        Commands::ListRepos(_) => {
+        Commands::ListRepos(subargs) => {
             if !config.database.filename.exists() {
                 eprintln!("missing database file");
                 exit(1)
             }
 
+            let db = open_db();
             let repos = match db.get_repo_list() {
                 Ok(r) => { r }
                 Err(e) => {
@@ -210,6 +1167,30 @@ fn main() -> Result<(), io::Error> {
                 }
             };
 
+            // GitHub only provides a 14 day window, so sum over the same period
+            let (window_start, _) = date_range(now_reference, 14);
+            let plain = subargs.plain || subargs.format.map(|f| f.is_machine_readable()).unwrap_or(false);
+
+            let summary = if plain {
+                None
+            } else {
+                match db.total_traffic(window_start) {
+                    Ok((views, clones)) => {
+                        Some(format!("{} repos, total 14-day views {}, clones {} across all",
+                                     repos.len(), views.count, clones.count))
+                    }
+                    Err(e) => {
+                        eprintln!("error getting traffic totals: {}", e);
+                        exit(1)
+                    }
+                }
+            };
+
+            if let Some(summary) = &summary {
+                println!("{}", summary);
+                println!();
+            }
+
             let mut widths: Vec<usize> = Vec::new();
             let mut rows: Vec<Vec<String>> = Vec::new();
 
@@ -218,14 +1199,43 @@ fn main() -> Result<(), io::Error> {
 
                 let url = format!("https://github.com/{}/{}", repo.owner, repo.name);
 
+                let (first_date, last_date) = match db.get_date_range(&repo.owner, &repo.name) {
+                    Ok(Some((first, last))) => (first.to_string(), last.to_string()),
+                    Ok(None) => (String::new(), String::new()),
+                    Err(e) => {
+                        eprintln!("error getting date range of {}: {}", repo.full_name(), e);
+                        exit(1)
+                    }
+                };
+
+                let totals = match subargs.totals {
+                    false => None,
+                    true => match db.get_repo_totals(&repo.owner, &repo.name) {
+                        Ok(t) => { Some(t) }
+                        Err(e) => {
+                            eprintln!("error getting totals of {}: {}", repo.full_name(), e);
+                            exit(1)
+                        }
+                    },
+                };
+
                 row.push(repo.owner);
                 row.push(repo.name);
                 row.push(url);
+                row.push(first_date);
+                row.push(last_date);
+
+                if let Some(totals) = totals {
+                    row.push(totals.total_views.to_string());
+                    row.push(totals.total_view_uniques.to_string());
+                    row.push(totals.total_clones.to_string());
+                    row.push(totals.total_clone_uniques.to_string());
+                }
 
                 if widths.is_empty() {
                     // Initial widths
-                    for i in 0..row.len() {
-                        widths.push(row[i].len());
+                    for rstr in &row {
+                        widths.push(rstr.len());
                     }
                 } else {
                     // Update widths
@@ -241,17 +1251,44 @@ fn main() -> Result<(), io::Error> {
                 rows.push(row);
             }
 
-            for row in rows {
-                println!("{0:1$} {2:>3$} {4:5$}",
-                         row[0], widths[0],
-                         row[1], widths[1],
-                         row[2], widths[2],
-                );
+            match subargs.format {
+                None => {
+                    for row in rows {
+                        // Repository names and totals aligned right
+                        let cells: Vec<String> = row
+                            .iter()
+                            .enumerate()
+                            .map(|(i, c)| match i {
+                                1 | 5.. => format!("{0:>1$}", c, widths[i]),
+                                _ => format!("{0:1$}", c, widths[i]),
+                            })
+                            .collect();
+
+                        println!("{}", cells.join(" "));
+                    }
+                }
+                Some(format) => {
+                    let mut columns: Vec<String> = ["owner", "repo", "url", "first_date", "last_date"].map(String::from).to_vec();
+
+                    if subargs.totals {
+                        columns.extend(["views", "views_unique", "clones", "clones_unique"].map(String::from));
+                    }
+
+                    print_rows(format, &columns, &rows);
+                }
+            }
+
+            if let Some(summary) = &summary {
+                println!();
+                println!("{}", summary);
             }
         } // /Command
 
         // Generate statistics SVG
         Commands::Stats(subargs) => {
+            let days = subargs.days.unwrap_or(default_days);
+            let chart_cfg = config.chart_config(&subargs.chart);
+
             if !config.database.filename.exists() {
                 eprintln!("missing database file");
                 exit(1)
@@ -262,22 +1299,85 @@ fn main() -> Result<(), io::Error> {
                 exit(1);
             }
 
-            match generate(&db, config.github.user, subargs.repo.clone(), now_reference, subargs.days) {
+            let db = open_db();
+            warn_future_dates(&db, now_reference);
+
+            let (owner, repo_name) = match resolve_repo(&db, &subargs.repo) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            };
+
+            if subargs.sparkline {
+                match print_sparkline(&db, owner, repo_name, now_reference, days) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        eprintln!("error getting repo {} {}", &subargs.repo, e);
+                        exit(1)
+                    }
+                };
+
+                return Ok(());
+            }
+
+            let kinds = match subargs.kind {
+                None => vec![Clones, Views],
+                Some(k) => vec![k.stat_type()],
+            };
+
+            // A single kind is required by clap when output is given
+            let output = match &subargs.output {
+                None => ChartOutput::StatsDir(subargs.output_dir.clone(), kinds),
+                Some(o) if o.as_os_str() == "-" => ChartOutput::Stdout(kinds[0]),
+                Some(o) => ChartOutput::File(kinds[0], o.clone()),
+            };
+
+            match generate(&db, owner.clone(), repo_name.clone(), now_reference, days, &chart_cfg, &output) {
                 Ok(_) => {}
                 Err(e) => {
                     eprintln!("error getting repo {} {}", &subargs.repo, e);
                     exit(1)
                 }
             };
+
+            // Keep standard output SVG only
+            if !matches!(output, ChartOutput::Stdout(_)) {
+                match db.get_repo_totals(&owner, &repo_name) {
+                    Ok(t) => {
+                        println!(
+                            "Total {} views ({} unique), {} clones ({} unique) of {}/{} in database",
+                            t.total_views, t.total_view_uniques, t.total_clones, t.total_clone_uniques, owner, repo_name,
+                        );
+                    }
+                    Err(e) => {
+                        eprintln!("error getting totals of {}: {}", &subargs.repo, e);
+                        exit(1)
+                    }
+                }
+            }
         } // /Command
 
         Commands::Generate(genargs) => {
+            let days = genargs.days.unwrap_or(default_days);
+            let chart_cfg = config.chart_config(&genargs.chart);
+
             if !config.database.filename.exists() {
                 eprintln!("missing database file");
                 exit(1)
             }
 
-            let repos = match db.get_repo_list() {
+            let db = open_db();
+            warn_future_dates(&db, now_reference);
+
+            let repos = if genargs.changed_only {
+                db.changed_repos()
+            } else {
+                db.get_repo_list()
+            };
+
+            let repos = match repos {
                 Ok(r) => { r }
                 Err(e) => {
                     eprintln!("error getting repo list: {}", e);
@@ -285,8 +1385,12 @@ fn main() -> Result<(), io::Error> {
                 }
             };
 
+            if genargs.changed_only && repos.is_empty() {
+                println!("No repositories changed in latest fetch.");
+            }
+
             for repo in repos {
-                match generate(&db, config.github.user.clone(), repo.name.clone(), now_reference, genargs.days) {
+                match generate(&db, repo.owner.clone(), repo.name.clone(), now_reference, days, &chart_cfg, &ChartOutput::StatsDir(genargs.output_dir.clone(), vec![Clones, Views])) {
                     Ok(_) => {}
                     Err(e) => {
                         eprintln!("error getting repo {} {}", repo.name, e);
@@ -295,131 +1399,1326 @@ fn main() -> Result<(), io::Error> {
                 };
             }
         } // /Command
-    }
 
-    // Ok
-    Ok(())
-}
+        Commands::Update(upargs) => {
+            let days = upargs.days.unwrap_or(default_days);
+            let chart_cfg = config.chart_config(&upargs.chart);
 
-// generate SVG chart for a repo
-fn generate(
-    db: &Database,
-    owner: String,
-    repo_name: String,
-    now_ref: NaiveDate,
-    days: u32,
-) -> Result<(), Box<dyn Error>> {
-    match db.repo_exists(&owner, &repo_name) {
-        Ok(exists) => {
-            if !exists {
-                eprintln!("repo named {} doesn't exist in local database", &repo_name);
-                exit(1);
-            }
-        }
-        Err(e) => {
-            eprintln!("error getting repo {} {}", &repo_name, e);
-            exit(1)
-        }
-    }
+            let mut ghsc = match github_client(&config, false, args.no_cache) {
+                Ok(g) => { g }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            };
+            ghsc.set_max_inflight(upargs.fetch_jobs as usize);
 
-    let stats = match db.get_repo_stats(&owner, &repo_name, now_ref, days) {
-        Ok(r) => { r }
-        Err(e) => {
-            eprintln!("error getting repo {} {}", &repo_name, e);
-            exit(1)
-        }
-    };
+            let db = open_db();
 
-    let fpath = PathBuf::from("stats");
-    if !fpath.exists() {
-        fs::create_dir_all(fpath.clone())?;
-    }
+            let repos = match fetch_repo_list(&ghsc, &config.github).and_then(|r| store_stars(&db, r)) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            };
 
-    for t in [Clones, Views] {
-        let n = match t {
-            Clones => "clones",
-            Views => "views",
-        };
+            if let Err(e) = db.clear_changed_repos() {
+                eprintln!("error clearing changed repositories: {}", e);
+                exit(1)
+            }
 
-        // Legend
-        let renames: HashMap<u8, String> = [
-            (0, "Count".to_string()),
-            (1, "Unique".to_string()),
-        ].iter().cloned().collect();
+            // --- Fetch phase: network bound, workers fetch and this thread writes to database
+            let queue = Mutex::new(repos.into_iter());
+            let (tx, rx) = mpsc::channel();
+
+            thread::scope(|s| {
+                for _ in 0..upargs.fetch_jobs {
+                    let tx = tx.clone();
+                    let (queue, ghsc) = (&queue, &ghsc);
+
+                    s.spawn(move || loop {
+                        let repo = match queue.lock().unwrap().next() {
+                            None => { break; }
+                            Some(r) => { r }
+                        };
+
+                        let fetch = |t| ghsc.get_stats(t, &repo.owner, &repo.name);
+                        let res = (
+                            fetch(Clones),
+                            fetch(Views),
+                            ghsc.get_referrers(&repo.owner, &repo.name),
+                            ghsc.get_popular_paths(&repo.owner, &repo.name),
+                        );
+
+                        if tx.send((repo, res)).is_err() {
+                            break;
+                        }
+                    });
+                }
 
-        let random_str = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+                drop(tx);
 
-        let tmpfname = PathBuf::from("cache")
-            .join(format!(".tmp-{}_{}_{}.svg", n, &repo_name, random_str))
-            ;
+                for (repo, res) in rx {
+                    println!("Repo https://github.com/{} :", repo.full_name());
 
-        let fname = fpath
-            .clone()
-            .join(format!("{}_{}.svg", &repo_name, n))
-            ;
+                    match res {
+                        (Ok(clone_stats), Ok(view_stats), Ok(referrers), Ok(paths)) => {
+                            if let Err(e) = store_traffic(&db, &repo, clone_stats, view_stats, &referrers, &paths, upargs.drop_partial) {
+                                eprintln!("error saving traffic of {}: {}", repo.full_name(), e);
+                                exit(1)
+                            }
+                        }
+                        (Err(e), _, _, _) | (_, Err(e), _, _) | (_, _, Err(e), _) | (_, _, _, Err(e)) => {
+                            eprintln!("error traffic {}: {}", repo.full_name(), e);
+                            exit(1)
+                        }
+                    }
+                }
+            });
 
-        let mut chart_gen: ChartGenerator = ChartGenerator::new(
-            format!("GitHub {} for {}", n, &repo_name),
-            tmpfname.clone(),
-            renames.clone(),
-            days,
-        );
+            println!("Database file {} updated.", config.database.filename.display());
+            print_api_warnings(&ghsc);
 
-        // Add clone and view count(s)
-        for (_, item) in stats.iter().enumerate() {
-            let mut m: HashMap<u8, u64> = HashMap::new();
+            warn_future_dates(&db, now_reference);
 
-            match t {
-                Clones => {
-                    m = [
-                        (0, item.clones.count),
-                        (1, item.clones.uniques),
-                    ].iter().cloned().collect();
-                }
-                Views => {
-                    m = [
-                        (0, item.views.count),
-                        (1, item.views.uniques),
-                    ].iter().cloned().collect();
+            // --- Render phase: CPU bound, each worker has its own database connection
+            let repos = match db.get_repo_list() {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("error getting repo list: {}", e);
+                    exit(1)
                 }
-            }
+            };
 
-            chart_gen.add(item.date, m);
-        } // /for
+            drop(db);
 
-        // Render SVG
-        match chart_gen.render() {
-            Ok(_) => {
-                println!(
-                    "Generated {} temp statistics SVG for repo {} as {}",
-                    n,
-                    &repo_name,
-                    tmpfname.clone().display(),
-                );
-            }
-            Err(e) => {
-                eprintln!("error generating {} SVG for repo {} {}", n, &repo_name, e);
+            let cpus = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+            let render_jobs = upargs.render_jobs.map_or(cpus, |n| (n as usize).min(cpus));
+
+            let queue = Mutex::new(repos.into_iter());
+
+            thread::scope(|s| {
+                for _ in 0..render_jobs {
+                    s.spawn(|| {
+                        let db = open_db();
+
+                        loop {
+                            let repo = match queue.lock().unwrap().next() {
+                                None => { break; }
+                                Some(r) => { r }
+                            };
+
+                            match generate(&db, repo.owner.clone(), repo.name.clone(), now_reference, days, &chart_cfg, &ChartOutput::StatsDir(PathBuf::from(DEFAULT_OUTPUT_DIR), vec![Clones, Views])) {
+                                Ok(_) => {}
+                                Err(e) => {
+                                    eprintln!("error getting repo {} {}", repo.name, e);
+                                    exit(1)
+                                }
+                            };
+                        }
+                    });
+                }
+            });
+        } // /Command
+
+        Commands::Report(_) => {
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
                 exit(1)
             }
-        };
 
-        // Move generated temporary file
-        match rename(tmpfname.clone(), fname.clone()) {
-            Ok(_) => {
-                println!(
-                    "Moved {} statistics SVG for repo {} {} to {}",
-                    n,
-                    &repo_name,
-                    tmpfname.clone().display(),
-                    fname.clone().display(),
-                );
+            let db = open_db();
+            let repos = match db.get_repo_list() {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("error getting repo list: {}", e);
+                    exit(1)
+                }
+            };
+
+            for repo in repos {
+                if let Err(e) = print_weekly_report(&db, &repo, now_reference) {
+                    eprintln!("error getting repo {} {}", repo.full_name(), e);
+                    exit(1)
+                }
             }
-            Err(e) => {
-                eprintln!("error moving {} to {}; {}", tmpfname.display(), fname.display(), e);
+        } // /Command
+
+        Commands::Status(subargs) => {
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
                 exit(1)
             }
-        };
-    }
 
-    Ok(())
-}
\ No newline at end of file
+            let rodb = match open_read_only_db(&config.database.filename) {
+                Ok(d) => { d }
+                Err(e) => {
+                    eprintln!("error opening database: {}", e);
+                    exit(1)
+                }
+            };
+
+            let (owner, repo_name) = match resolve_repo(&rodb, &subargs.repo) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            };
+
+            let (from, _) = date_range(now_reference, subargs.days);
+
+            let status = rodb.repo_traffic(&owner, &repo_name, from)
+                .and_then(|traffic| {
+                    let last = rodb.last_fetch(&owner, &repo_name)?;
+                    Ok((traffic, last))
+                });
+
+            let ((views, clones), last_fetch) = match status {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("error getting status of {}: {}", subargs.repo, e);
+                    exit(1)
+                }
+            };
+
+            let last_fetch = match last_fetch {
+                None => String::from("never"),
+                Some(t) => format!("{} ago", short_duration(Utc::now() - t)),
+            };
+
+            println!("{}: {}v/{}c ({}d), last fetch {}",
+                     subargs.repo, views.count, clones.count, subargs.days, last_fetch);
+        }
+
+        Commands::Export(subargs) => {
+            let days = subargs.days.unwrap_or(default_days);
+
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
+                exit(1)
+            }
+
+            let rodb = match open_read_only_db(&config.database.filename) {
+                Ok(d) => { d }
+                Err(e) => {
+                    eprintln!("error opening database: {}", e);
+                    exit(1)
+                }
+            };
+
+            let (owner, repo_name) = match resolve_repo(&rodb, &subargs.repo) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            };
+
+            let mut stats = match rodb.get_repo_stats(&owner, &repo_name, now_reference, days) {
+                Ok(s) => { s }
+                Err(e) => {
+                    eprintln!("error getting repo {} {}", &subargs.repo, e);
+                    exit(1)
+                }
+            };
+
+            // Oldest first
+            stats.sort_by_key(|s| s.date);
+
+            let text = match subargs.format {
+                OutputFormat::Json => serde_json::to_string_pretty(&stats).expect("JSON") + "\n",
+                format => {
+                    let rows: Vec<Vec<String>> = stats.iter()
+                        .map(|s| vec![
+                            s.date.to_string(),
+                            s.views.count.to_string(),
+                            s.views.uniques.to_string(),
+                            s.clones.count.to_string(),
+                            s.clones.uniques.to_string(),
+                        ])
+                        .collect();
+
+                    format_rows(format, &totals_columns(Some("date")), &rows)
+                }
+            };
+
+            match &subargs.output {
+                None => print!("{}", text),
+                Some(f) => {
+                    if let Err(e) = fs::write(f, text) {
+                        eprintln!("error writing {}: {}", f.display(), e);
+                        exit(1)
+                    }
+                }
+            }
+        } // /Command
+
+        Commands::ExportCsv(subargs) => {
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
+                exit(1)
+            }
+
+            let rodb = match open_read_only_db(&config.database.filename) {
+                Ok(d) => { d }
+                Err(e) => {
+                    eprintln!("error opening database: {}", e);
+                    exit(1)
+                }
+            };
+
+            let repo = match &subargs.repo {
+                None => None,
+                Some(r) => match resolve_repo(&rodb, r) {
+                    Ok(r) => { Some(r) }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        exit(1)
+                    }
+                },
+            };
+
+            let output = match (&subargs.output, &repo) {
+                (Some(f), _) if f.as_os_str() == "-" => None,
+                (Some(f), _) => Some(f.clone()),
+                (None, Some((_, repo_name))) => Some(PathBuf::from(format!("{}_stats.csv", path_component(repo_name)))),
+                (None, None) => None,
+            };
+
+            let mut writer: Box<dyn Write> = match &output {
+                None => Box::new(io::stdout().lock()),
+                Some(f) => match File::create(f) {
+                    Ok(file) => { Box::new(io::BufWriter::new(file)) }
+                    Err(e) => {
+                        eprintln!("error creating {}: {}", f.display(), e);
+                        exit(1)
+                    }
+                },
+            };
+
+            let res = match &repo {
+                None => rodb.export_csv(&mut writer, subargs.from, subargs.to),
+                Some((owner, repo_name)) => rodb.export_repo_csv(owner, repo_name, &mut writer),
+            };
+
+            match res {
+                Ok(rows) => {
+                    // Standard output has only CSV
+                    if let Some(f) = &output {
+                        println!("Exported {} rows to {}", rows, f.display());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("error exporting CSV: {}", e);
+                    exit(1)
+                }
+            }
+        } // /Command
+
+        Commands::ImportCsv(subargs) => {
+            let mut reader: Box<dyn Read> = if subargs.file.as_os_str() == "-" {
+                Box::new(io::stdin().lock())
+            } else {
+                match File::open(&subargs.file) {
+                    Ok(f) => { Box::new(f) }
+                    Err(e) => {
+                        eprintln!("error opening {}: {}", subargs.file.display(), e);
+                        exit(1)
+                    }
+                }
+            };
+
+            let db = open_db();
+            let repo = &subargs.repo;
+
+            match db.import_csv(&repo.owner, &repo.name, &mut reader) {
+                Ok(rows) => {
+                    println!("Imported {} days of {}", rows, repo.full_name());
+                }
+                Err(e) => {
+                    eprintln!("error importing CSV: {}", e);
+                    exit(1)
+                }
+            }
+        } // /Command
+
+        Commands::Totals(subargs) => {
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
+                exit(1)
+            }
+
+            let rodb = match open_read_only_db(&config.database.filename) {
+                Ok(d) => { d }
+                Err(e) => {
+                    eprintln!("error opening database: {}", e);
+                    exit(1)
+                }
+            };
+
+            let (owner, repo_name) = match resolve_repo(&rodb, &subargs.repo) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            };
+
+            // Default date is 1970-01-01, before any stored traffic
+            let from = match subargs.days {
+                None => NaiveDate::default(),
+                Some(days) => date_range(now_reference, days).0,
+            };
+
+            let (views, clones) = match rodb.repo_traffic(&owner, &repo_name, from) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("error getting totals of {}: {}", subargs.repo, e);
+                    exit(1)
+                }
+            };
+
+            match subargs.format {
+                None => print_totals(&views, &clones, subargs.shell),
+                Some(format) => {
+                    let row = [views.count, views.uniques, clones.count, clones.uniques]
+                        .iter()
+                        .map(u64::to_string)
+                        .collect();
+
+                    print_rows(format, &totals_columns(None), &[row]);
+                }
+            }
+        }
+
+        Commands::Query(qargs) => {
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
+                exit(1)
+            }
+
+            let keyword = qargs.sql
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_uppercase();
+
+            if keyword != "SELECT" && keyword != "PRAGMA" {
+                eprintln!("only SELECT and PRAGMA statements are allowed");
+                exit(1)
+            }
+
+            let rodb = match open_read_only_db(&config.database.filename) {
+                Ok(d) => { d }
+                Err(e) => {
+                    eprintln!("error opening database: {}", e);
+                    exit(1)
+                }
+            };
+
+            let (columns, rows) = match rodb.query(&qargs.sql) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("query error: {}", e);
+                    exit(1)
+                }
+            };
+
+            let format = if qargs.csv {
+                warn!("--csv is deprecated, use --format csv");
+                OutputFormat::Csv
+            } else {
+                qargs.format
+            };
+
+            print_rows(format, &columns, &rows);
+        } // /Command
+
+        Commands::Paths(subargs) => {
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
+                exit(1)
+            }
+
+            let rodb = match open_read_only_db(&config.database.filename) {
+                Ok(d) => { d }
+                Err(e) => {
+                    eprintln!("error opening database: {}", e);
+                    exit(1)
+                }
+            };
+
+            let (owner, repo_name) = match resolve_repo(&rodb, &subargs.repo) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1)
+                }
+            };
+
+            let paths = match rodb.get_popular_paths(&owner, &repo_name, subargs.limit) {
+                Ok(r) => { r }
+                Err(e) => {
+                    eprintln!("error getting popular paths of {}: {}", subargs.repo, e);
+                    exit(1)
+                }
+            };
+
+            let columns: Vec<String> = ["path", "title", "views", "unique"].iter().map(|c| c.to_string()).collect();
+            let rows: Vec<Vec<String>> = paths
+                .into_iter()
+                .map(|p| vec![p.path, p.title, p.count.to_string(), p.uniques.to_string()])
+                .collect();
+
+            print_rows(subargs.format, &columns, &rows);
+        } // /Command
+
+        Commands::Reindex => {
+            let stats_dir = GithubStats::stats_cache_dir(&config.cache.dir());
+
+            let files = match GithubStats::cached_stats_files(&stats_dir) {
+                Ok(f) => { f }
+                Err(e) => {
+                    eprintln!("error reading cache dir {}: {}", stats_dir.display(), e);
+                    exit(1)
+                }
+            };
+
+            let db = open_db();
+
+            let mut repos: HashSet<String> = HashSet::new();
+            let mut days: usize = 0;
+
+            for (repo, t, path) in files {
+                let stats = match fs::read_to_string(&path) {
+                    Ok(json) => GithubStats::parse_stats(t, &json),
+                    Err(e) => Err(e.into()),
+                };
+
+                // Broken files are skipped, the rest of the history is still worth recovering
+                let stats = match stats {
+                    Ok(s) => { s }
+                    Err(e) => {
+                        eprintln!("skipping {}: {}", path.display(), e);
+                        continue;
+                    }
+                };
+
+                let changed = match db.update_traffic(t, &repo.owner, &repo.name, stats.days) {
+                    Ok(n) => { n }
+                    Err(e) => {
+                        eprintln!("error saving {} of {}: {}", t.name(), repo.full_name(), e);
+                        exit(1)
+                    }
+                };
+                println!("  {}: {} days of {} changed", repo.full_name(), changed, t.name());
+
+                if changed > 0 {
+                    if let Err(e) = db.mark_changed(&repo.owner, &repo.name) {
+                        eprintln!("error saving changed repository: {}", e);
+                        exit(1)
+                    }
+                }
+
+                repos.insert(repo.full_name());
+                days += changed;
+            }
+
+            println!("Reindexed {} repositories, {} days changed.", repos.len(), days);
+        }
+
+        Commands::CheckDb => {
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
+                exit(1)
+            }
+
+            // Not upgraded, only read
+            let rodb = match Database::open_read_only(&config.database.filename) {
+                Ok(d) => { d }
+                Err(e) => {
+                    eprintln!("error opening database: {}", e);
+                    exit(1)
+                }
+            };
+
+            let mut ok = true;
+
+            for (name, res) in [("quick_check", rodb.quick_check()), ("integrity_check", rodb.check_integrity())] {
+                match res {
+                    Ok(lines) if lines == ["ok"] => {
+                        println!("{}: ok", name);
+                    }
+                    Ok(lines) => {
+                        ok = false;
+                        println!("{}:", name);
+
+                        for line in lines {
+                            println!("  {}", line);
+                        }
+                    }
+                    Err(e) => {
+                        ok = false;
+                        eprintln!("{}: {}", name, e);
+                    }
+                }
+            }
+
+            if !ok {
+                exit(1)
+            }
+        } // /Command
+
+        Commands::ResetDb(resetargs) => {
+            if !resetargs.yes {
+                eprintln!("this replaces {} with an empty database, use --yes to confirm", config.database.filename.display());
+                exit(1)
+            }
+
+            // Not opened first, the database might be corrupted
+            match Database::recreate(&config.database.filename) {
+                Ok((_, backup)) => {
+                    println!("Database file {} recreated, backup saved as {}", config.database.filename.display(), backup.display());
+                }
+                Err(e) => {
+                    eprintln!("error recreating database: {}", e);
+                    exit(1)
+                }
+            }
+        } // /Command
+
+        Commands::ClearCache(clearargs) => {
+            let removed = match clear_cache(&config.cache.dir(), clearargs.repo.as_deref(), clearargs.dry_run) {
+                Ok(n) => { n }
+                Err(e) => {
+                    eprintln!("error clearing cache: {}", e);
+                    exit(1)
+                }
+            };
+
+            if clearargs.dry_run {
+                println!("Would remove {} cache files.", removed);
+            } else {
+                println!("Removed {} cache files.", removed);
+            }
+        } // /Command
+
+        Commands::DeleteRepo(delargs) => {
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
+                exit(1)
+            }
+
+            let db = open_db();
+
+            // Exact name only, a deletion must not guess which repository was meant
+            let owner = delargs.repo.owner.clone();
+            let repo_name = delargs.repo.name.clone();
+
+            if !delargs.confirm {
+                eprintln!("this deletes all data of {}/{} from {}, use --confirm to confirm", owner, repo_name, config.database.filename.display());
+                exit(1)
+            }
+
+            let rows = match db.delete_repo(&owner, &repo_name) {
+                Ok(n) => { n }
+                Err(e) => {
+                    eprintln!("error deleting repo {}/{}: {}", owner, repo_name, e);
+                    exit(1)
+                }
+            };
+
+            let files = match clear_cache(&config.cache.dir(), Some(&format!("{}/{}", owner, repo_name)), false) {
+                Ok(n) => { n }
+                Err(e) => {
+                    eprintln!("error clearing cache of {}/{}: {}", owner, repo_name, e);
+                    exit(1)
+                }
+            };
+
+            // Nothing matched, most likely a typo in the name
+            if rows == 0 && files == 0 {
+                eprintln!("repo {}/{} not found in local database or cache", owner, repo_name);
+                exit(1)
+            }
+
+            println!("Deleted {} database rows and {} cache files of {}/{}.", rows, files, owner, repo_name);
+        } // /Command
+
+        Commands::RenameRepo(renameargs) => {
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
+                exit(1)
+            }
+
+            let db = open_db();
+            let repo = &renameargs.repo;
+
+            if renameargs.new_name.is_empty() || renameargs.new_name.contains('/') {
+                eprintln!("new name must be a repository name without owner, got {}", renameargs.new_name);
+                exit(1)
+            }
+
+            let rows = match db.rename_repo(&repo.owner, &repo.name, &renameargs.new_name) {
+                Ok(n) => { n }
+                Err(e) => {
+                    eprintln!("error renaming repo {}: {}", repo.full_name(), e);
+                    exit(1)
+                }
+            };
+
+            println!("Moved {} days of traffic from {} to {}/{}.", rows, repo.full_name(), repo.owner, renameargs.new_name);
+        } // /Command
+
+        Commands::Prune(pruneargs) => {
+            if !config.database.filename.exists() {
+                eprintln!("missing database file");
+                exit(1)
+            }
+
+            let db = open_db();
+
+            let repo = match &pruneargs.repo {
+                None => None,
+                Some(r) => match resolve_repo(&db, r) {
+                    Ok(r) => { Some(r) }
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        exit(1)
+                    }
+                },
+            };
+
+            let rows = match db.prune_old_data(pruneargs.before, repo.as_ref().map(|(o, r)| (o.as_str(), r.as_str())), pruneargs.dry_run) {
+                Ok(n) => { n }
+                Err(e) => {
+                    eprintln!("error pruning database: {}", e);
+                    exit(1)
+                }
+            };
+
+            if pruneargs.dry_run {
+                println!("Would delete {} rows dated before {}.", rows, pruneargs.before);
+            } else {
+                println!("Deleted {} rows dated before {}.", rows, pruneargs.before);
+            }
+        } // /Command
+    }
+
+    // Ok
+    Ok(())
+}
+
+// Lowercase file name part from a display name, "My Cool Library" -> "my-cool-library"
+fn filename_slug(name: &str) -> String {
+    let slug = name
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+
+    path_component(&slug)
+}
+
+// Open database read-only. An old schema isn't upgraded, that would write to the file.
+fn open_read_only_db(database_file: &PathBuf) -> Result<Database, GithubStatsError> {
+    let rodb = Database::open_read_only(database_file)?;
+    let version = rodb.schema_version()?;
+
+    if version < SCHEMA_VERSION {
+        Err(GithubStatsError::InvalidInput(format!(
+            "database schema version {} is older than {}, run a command which writes to the database first, for example fetch or reindex",
+            version, SCHEMA_VERSION,
+        )))?
+    }
+
+    Ok(rodb)
+}
+
+// Print totals as lines, or as shell variable assignments for eval or source
+fn print_totals(views: &Stats, clones: &Stats, shell: bool) {
+    let totals = TOTALS_COLUMNS.iter().zip([views.count, views.uniques, clones.count, clones.uniques]);
+
+    for (name, value) in totals {
+        if shell {
+            println!("GHSTATS_{}={}", name.to_uppercase(), value);
+        } else {
+            println!("{}: {}", name, value);
+        }
+    }
+}
+
+// Names of view and clone counts in totals and export rows
+const TOTALS_COLUMNS: [&str; 4] = ["views", "views_unique", "clones", "clones_unique"];
+
+// Column names for rows of totals, optionally after a key column
+fn totals_columns(key: Option<&str>) -> Vec<String> {
+    key.into_iter().chain(TOTALS_COLUMNS).map(String::from).collect()
+}
+
+// Days stored after today can't be charted, usually caused by a wrong system clock
+fn warn_future_dates(db: &Database, now_ref: NaiveDate) {
+    match db.future_dates(now_ref) {
+        Ok(dates) => {
+            for (repo, date) in dates {
+                warn!("{} has stats for {} which is after {}, check system clock", repo.full_name(), date, now_ref);
+            }
+        }
+        Err(e) => {
+            warn!("couldn't check for future dates: {}", e);
+        }
+    }
+}
+
+// Resolve "owner/repo" or a repository name which only one owner has in database
+fn resolve_repo(db: &Database, arg: &str) -> Result<(String, String), GithubStatsError> {
+    if let Some((owner, repo_name)) = arg.split_once('/') {
+        if owner.is_empty() || repo_name.is_empty() {
+            Err(GithubStatsError::InvalidInput(format!("invalid repository {}, use owner/repo or repo", arg)))?
+        }
+
+        return Ok((owner.to_string(), repo_name.to_string()));
+    }
+
+    let owners = db.repo_owners(arg)?;
+
+    match owners.len() {
+        0 => Err(GithubStatsError::NotFound(format!("repo named {} doesn't exist in local database", arg))),
+        1 => Ok((owners[0].clone(), arg.to_string())),
+        _ => Err(GithubStatsError::InvalidInput(format!(
+            "repo name {} is ambiguous, use one of: {}",
+            arg,
+            owners.iter().map(|o| format!("{}/{}", o, arg)).collect::<Vec<String>>().join(", "),
+        ))),
+    }
+}
+
+// Which charts generate writes and where
+enum ChartOutput {
+    // Output directory, stats by default, file name from repository and kind
+    StatsDir(PathBuf, Vec<StatType>),
+    // Single chart to a file
+    File(StatType, PathBuf),
+    // Single chart to standard output
+    Stdout(StatType),
+}
+
+impl ChartOutput {
+    fn kinds(&self) -> Vec<StatType> {
+        match self {
+            ChartOutput::StatsDir(_, kinds) => kinds.clone(),
+            ChartOutput::File(kind, _) | ChartOutput::Stdout(kind) => vec![*kind],
+        }
+    }
+}
+
+// generate SVG or PNG chart for a repo
+fn generate(
+    db: &CachedDatabase,
+    owner: String,
+    repo_name: String,
+    now_ref: NaiveDate,
+    days: u32,
+    chart_cfg: &ConfigChart,
+    output: &ChartOutput,
+) -> Result<(), GithubStatsError> {
+    if !db.repo_exists(&owner, &repo_name)? {
+        Err(GithubStatsError::NotFound(format!("repo named {} doesn't exist in local database", &repo_name)))?
+    }
+
+    let stats = db.get_repo_stats(&owner, &repo_name, now_ref, days)?;
+
+    let fpath = match output {
+        ChartOutput::StatsDir(dir, _) if chart_cfg.flat_layout.unwrap_or(false) => dir.clone(),
+        ChartOutput::StatsDir(dir, _) => dir.join(path_component(&owner)),
+        ChartOutput::File(_, f) => match f.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+        ChartOutput::Stdout(_) => PathBuf::new(),
+    };
+
+    // Fail early with a clear message on read-only deployments
+    if !matches!(output, ChartOutput::Stdout(_)) {
+        ensure_writable_dir(&fpath)?;
+    }
+
+    let opts = chart_cfg.chart_options(&owner, &repo_name)?;
+    let ext = opts.output_format.extension();
+    let format_name = ext.to_uppercase();
+
+    if let (ChartOutput::Stdout(_), ImageFormat::Png) = (output, opts.output_format) {
+        Err(GithubStatsError::InvalidInput(String::from("PNG can't be written to standard output, use --output FILE")))?
+    }
+
+    // Display name from [titles]
+    let file_base = match &opts.title {
+        Some(t) if chart_cfg.title_filenames.unwrap_or(false) => filename_slug(t),
+        _ => repo_name.clone(),
+    };
+
+    for t in output.kinds() {
+        let n = t.name();
+
+        let random_str = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+
+        // Temporary file next to the target so that rename stays on the same filesystem
+        let tmpfname = fpath
+            .clone()
+            .join(format!(".tmp-{}_{}_{}.{}", n, &file_base, random_str, ext))
+            ;
+
+        let fname = match output {
+            ChartOutput::File(_, f) => f.clone(),
+            _ => fpath.join(format!("{}_{}.{}", &file_base, n, ext)),
+        };
+
+        let mut chart_gen = chart_from_stats(&stats, &owner, &repo_name, t, now_ref, days, &opts);
+        chart_gen.set_filename(tmpfname.clone());
+
+        if let ChartOutput::Stdout(_) = output {
+            let stdout = io::stdout();
+            chart_gen.render_to_writer(&mut stdout.lock())
+                .map_err(|e| GithubStatsError::ChartError(e.to_string()))?;
+            continue;
+        }
+
+        // Skip if existing chart was rendered from the same data
+        if let (false, Ok(existing)) = (chart_cfg.force, fs::read_to_string(&fname)) {
+            if embedded_hash(&existing) == Some(chart_gen.content_hash()) {
+                println!(
+                    "Unchanged {} statistics {} for repo {} {}",
+                    n,
+                    format_name,
+                    &repo_name,
+                    fname.display(),
+                );
+
+                continue;
+            }
+        }
+
+        // Render SVG or PNG
+        match chart_gen.render() {
+            Ok(_) => {
+                println!(
+                    "Generated {} temp statistics {} for repo {} as {}",
+                    n,
+                    format_name,
+                    &repo_name,
+                    tmpfname.clone().display(),
+                );
+            }
+            Err(e) => {
+                Err(GithubStatsError::ChartError(format!("{} {} for repo {}: {}", n, format_name, &repo_name, e)))?
+            }
+        };
+
+        // Move generated temporary file
+        match move_file(&tmpfname, &fname) {
+            Ok(_) => {
+                println!(
+                    "Moved {} statistics {} for repo {} {} to {}",
+                    n,
+                    format_name,
+                    &repo_name,
+                    tmpfname.clone().display(),
+                    fname.clone().display(),
+                );
+            }
+            Err(e) => {
+                Err(with_context(e, format!("failed to move `{}` to `{}`", tmpfname.display(), fname.display())))?
+            }
+        };
+    }
+
+    Ok(())
+}
+
+// Print sparklines of a repo's traffic to terminal
+fn print_sparkline(
+    db: &CachedDatabase,
+    owner: String,
+    repo_name: String,
+    now_ref: NaiveDate,
+    days: u32,
+) -> Result<(), Box<dyn Error>> {
+    if !db.repo_exists(&owner, &repo_name)? {
+        Err(format!("repo named {} doesn't exist in local database", &repo_name))?
+    }
+
+    let stats = db.get_repo_stats(&owner, &repo_name, now_ref, days)?;
+
+    for t in [Views, Clones] {
+        let n = t.name();
+
+        let data: HashMap<NaiveDate, u64> = stats
+            .iter()
+            .filter_map(|item| item.get(t).map(|s| (item.date, s.count)))
+            .collect();
+
+        let values = daily_values(&data, now_ref, days);
+        let total: u64 = values.iter().sum();
+        let avg = if days == 0 { 0.0 } else { total as f64 / days as f64 };
+
+        println!(
+            "{:6} {} total {}, avg {:.1}/day",
+            n,
+            sparkline(&values),
+            total,
+            avg,
+        );
+    }
+
+    Ok(())
+}
+
+// Print last 7 days of a repo's traffic compared to 7 days before
+fn print_weekly_report(
+    db: &CachedDatabase,
+    repo: &Repo,
+    now_ref: NaiveDate,
+) -> Result<(), Box<dyn Error>> {
+    const WEEK: usize = 7;
+
+    let stats = db.get_repo_stats(&repo.owner, &repo.name, now_ref, 2 * WEEK as u32)?;
+
+    println!("{}", repo.full_name());
+
+    for t in [Views, Clones] {
+        let data: HashMap<NaiveDate, u64> = stats
+            .iter()
+            .filter_map(|item| item.get(t).map(|s| (item.date, s.count)))
+            .collect();
+
+        // Oldest first, so prior week comes first
+        let values = daily_values(&data, now_ref, 2 * WEEK as u32);
+        let (prior, current) = values.split_at(WEEK);
+
+        let prior_total: u64 = prior.iter().sum();
+        let total: u64 = current.iter().sum();
+
+        let delta = if prior_total == 0 {
+            String::from("n/a")
+        } else {
+            format!("{:+.0}%", (total as f64 - prior_total as f64) * 100.0 / prior_total as f64)
+        };
+
+        println!(
+            "  {:7} last 7d {} (Δ {} vs prior 7d) {}",
+            format!("{}:", t.name()),
+            total,
+            delta,
+            sparkline(current),
+        );
+    }
+
+    Ok(())
+}
+
+// Write fetched traffic of a repository to database
+fn store_traffic(
+    db: &Database,
+    repo: &Repo,
+    mut clone_stats: TrafficStats,
+    mut view_stats: TrafficStats,
+    referrers: &[ReferrerStats],
+    paths: &[PopularPath],
+    drop_partial: Option<PartialDays>,
+) -> Result<(), GithubStatsError> {
+    // All writes of the repository are committed at once
+    db.transaction(|| {
+        drop_partial_days(&mut clone_stats.days, drop_partial);
+
+        let mut repo_changed = 0;
+
+        if !clone_stats.days.is_empty() {
+            println!("  Updating clones...");
+            let changed = db.update_traffic(Clones, &repo.owner, &repo.name, clone_stats.days)?;
+            println!("  {} days of clones changed", changed);
+            repo_changed += changed;
+        }
+
+        drop_partial_days(&mut view_stats.days, drop_partial);
+
+        if !view_stats.days.is_empty() {
+            println!("  Updating views...");
+            let changed = db.update_traffic(Views, &repo.owner, &repo.name, view_stats.days)?;
+            println!("  {} days of views changed", changed);
+            repo_changed += changed;
+        }
+
+        if repo_changed > 0 {
+            db.mark_changed(&repo.owner, &repo.name)?;
+        }
+
+        // Referrers and paths are snapshots of the whole window, stored per fetch day
+        let today = Utc::now().date_naive();
+
+        let stored = db.update_referrers(&repo.owner, &repo.name, today, referrers)?;
+        println!("  {} top referrers stored", stored);
+
+        let stored = db.update_popular_paths(&repo.owner, &repo.name, today, paths)?;
+        println!("  {} popular paths stored", stored);
+
+        db.set_last_fetch(&repo.owner, &repo.name, Utc::now())?;
+
+        Ok::<(), GithubStatsError>(())
+    })?;
+
+    // Totals from GitHub for its 14 day window
+    println!(
+        "  {}: {} views / {} clones",
+        repo.full_name(),
+        view_stats.count,
+        clone_stats.count,
+    );
+
+    Ok(())
+}
+
+// Remove partial day(s) from the edges of the traffic window
+fn drop_partial_days(stats: &mut Vec<DayStats>, edge: Option<PartialDays>) {
+    // API returns days in ascending order, but don't rely on it
+    stats.sort_by_key(|s| s.timestamp);
+
+    let (first, last) = match edge {
+        None => (false, false),
+        Some(PartialDays::First) => (true, false),
+        Some(PartialDays::Last) => (false, true),
+        Some(PartialDays::Both) => (true, true),
+    };
+
+    if last {
+        stats.pop();
+    }
+
+    if first && !stats.is_empty() {
+        stats.remove(0);
+    }
+}
+
+// Rows as a table with aligned columns
+fn format_table(columns: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+
+    for row in rows {
+        for (i, v) in row.iter().enumerate() {
+            widths[i] = widths[i].max(v.chars().count());
+        }
+    }
+
+    let line = |vals: &[String]| {
+        vals.iter()
+            .enumerate()
+            .map(|(i, v)| format!("{0:1$}", v, widths[i]))
+            .collect::<Vec<String>>()
+            .join(" ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut lines = vec![
+        line(columns),
+        widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<String>>().join(" "),
+    ];
+
+    lines.extend(rows.iter().map(|row| line(row)));
+    lines.join("\n") + "\n"
+}
+
+// Rows in given format, ending with a newline
+fn format_rows(format: OutputFormat, columns: &[String], rows: &[Vec<String>]) -> String {
+    match format {
+        OutputFormat::Table => format_table(columns, rows),
+        OutputFormat::Csv => format_csv(columns, rows),
+        OutputFormat::Json => format_json(columns, rows),
+        OutputFormat::Markdown => format_markdown(columns, rows),
+    }
+}
+
+// Print rows in given format
+fn print_rows(format: OutputFormat, columns: &[String], rows: &[Vec<String>]) {
+    print!("{}", format_rows(format, columns, rows));
+}
+
+// Rows as JSON array of objects
+fn format_json(columns: &[String], rows: &[Vec<String>]) -> String {
+    let objects: Vec<serde_json::Map<String, serde_json::Value>> = rows
+        .iter()
+        .map(|row| {
+            columns.iter()
+                .cloned()
+                .zip(row.iter().map(|v| serde_json::Value::String(v.clone())))
+                .collect()
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&objects).expect("JSON") + "\n"
+}
+
+// Rows as Markdown table
+fn format_markdown(columns: &[String], rows: &[Vec<String>]) -> String {
+    // Pipes would split cells
+    let line = |vals: &[String]| {
+        format!("| {} |", vals.iter().map(|v| v.replace('|', "\\|")).collect::<Vec<String>>().join(" | "))
+    };
+
+    let mut lines = vec![line(columns), format!("|{}", "---|".repeat(columns.len()))];
+    lines.extend(rows.iter().map(|row| line(row)));
+    lines.join("\n") + "\n"
+}
+
+// Rows as CSV
+fn format_csv(columns: &[String], rows: &[Vec<String>]) -> String {
+    // Quote fields containing separators, quotes or newlines
+    let field = |v: &String| {
+        if v.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", v.replace('"', "\"\""))
+        } else {
+            v.clone()
+        }
+    };
+
+    let mut lines = vec![columns.iter().map(field).collect::<Vec<String>>().join(",")];
+    lines.extend(rows.iter().map(|row| row.iter().map(field).collect::<Vec<String>>().join(",")));
+    lines.join("\n") + "\n"
+}
+
+// GitHub API client from config
+fn github_client(cfg: &Config, offline: bool, no_cache: bool) -> Result<GithubStats, GithubStatsError> {
+    if offline && no_cache {
+        Err(GithubStatsError::InvalidInput(String::from("--offline needs cached responses, it can't be used with --no-cache")))?
+    }
+
+    let config = &cfg.github;
+    let mut options = cfg.fetch.as_ref().map(ConfigFetch::options).unwrap_or_default();
+
+    if let Some(ttl) = cfg.cache.ttl() {
+        options.cache_ttl = ttl;
+    }
+
+    match (config.user.is_empty(), &config.org) {
+        (true, None) => {
+            Err(GithubStatsError::InvalidInput(String::from("no GitHub user or org in config file")))?
+        }
+        (false, Some(_)) => {
+            Err(GithubStatsError::InvalidInput(String::from("both GitHub user and org in config file, set only one")))?
+        }
+        _ => {}
+    }
+
+    // API key isn't needed when everything comes from cache
+    if config.apikey.is_empty() && !offline {
+        Err(GithubStatsError::InvalidInput(String::from("no GitHub API key in config file")))?
+    }
+
+    let mut ghsc = match &config.proxy {
+        None => GithubStats::new(&config.apikey, options),
+        Some(proxy) => GithubStats::with_proxy(&config.apikey, proxy, options)?,
+    };
+
+    ghsc.set_offline(offline);
+    ghsc.set_no_cache(no_cache);
+    ghsc.set_cache_dir(&cfg.cache.dir());
+    let mut retry = cfg.retry.policy();
+
+    if let Some(count) = cfg.fetch.as_ref().and_then(|f| f.retry_count) {
+        retry.attempts = count.saturating_add(1);
+    }
+
+    ghsc.set_retry(retry);
+
+    if let Some(api_base) = &config.api_base {
+        ghsc.set_api_base(api_base);
+    }
+
+    if let Some(version) = &config.api_version {
+        ghsc.set_api_version(version);
+    }
+
+    for media_type in &config.accept {
+        ghsc.add_accept(media_type);
+    }
+
+    Ok(ghsc)
+}
+
+// Largest whole unit of a duration, for example 3h
+fn short_duration(d: chrono::Duration) -> String {
+    let secs = d.num_seconds().max(0);
+
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / (60 * 60)),
+        s => format!("{}d", s / (24 * 60 * 60)),
+    }
+}
+
+// Get repositories to fetch stats for, with their current star counts
+fn fetch_repo_list(ghsc: &GithubStats, config: &ConfigGitHub) -> Result<Vec<(Repo, Option<u64>)>, GithubStatsError> {
+    let mut repos: GhRepo = match &config.org {
+        Some(org) => {
+            println!("Fetching repository list for https://github.com/{} ..", org);
+            ghsc.get_org_repositories(org.clone())?
+        }
+        None => {
+            println!("Fetching repository list for https://github.com/{} ..", config.user);
+            ghsc.get_repositories(config.user.clone())?
+        }
+    };
+
+    if config.org_member_repos {
+        println!("Fetching organization member repository list ..");
+        let member_repos: GhRepo = ghsc.get_member_repositories()?;
+
+        for repo in member_repos {
+            if !repos.iter().any(|r| r.full_name == repo.full_name) {
+                repos.push(repo);
+            }
+        }
+    }
+
+    Ok(repos
+        .into_iter()
+        .map(|r| (
+            Repo {
+                owner: r.owner.login,
+                name: r.name,
+            },
+            Some(r.stargazers_count),
+        ))
+        .collect())
+}
+
+// Snapshot today's star counts, repositories without a count are skipped
+fn store_stars(db: &Database, repos: Vec<(Repo, Option<u64>)>) -> Result<Vec<Repo>, GithubStatsError> {
+    let today = Utc::now().date_naive();
+    let mut res: Vec<Repo> = Vec::new();
+
+    for (repo, stars) in repos {
+        if let Some(count) = stars {
+            db.update_stars(&repo.owner, &repo.name, today, count)?;
+        }
+
+        res.push(repo);
+    }
+
+    Ok(res)
+}
+
+// Print deprecation warnings collected from GitHub API responses
+fn print_api_warnings(ghsc: &GithubStats) {
+    let api_warnings = ghsc.api_warnings();
+
+    if !api_warnings.is_empty() {
+        eprintln!("GitHub API deprecation warnings, github-stats might need to be updated:");
+        for w in api_warnings {
+            eprintln!("  {}", w);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use chrono::NaiveDate;
+use crate::date_range;
+
+// Block characters from lowest to highest value
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// Map values to a string of block characters, scaled to the largest value
+pub fn sparkline(values: &[u64]) -> String {
+    let max = values.iter().copied().max().unwrap_or(0);
+    let top = (BLOCKS.len() - 1) as u64;
+
+    values
+        .iter()
+        .map(|v| {
+            if max == 0 {
+                return BLOCKS[0];
+            }
+
+            // Round to nearest block
+            BLOCKS[((v * top + max / 2) / max) as usize]
+        })
+        .collect()
+}
+
+// Values for last N days, oldest first. Days missing from data are zero.
+pub fn daily_values(
+    data: &HashMap<NaiveDate, u64>,
+    now_ref: NaiveDate,
+    days: u32,
+) -> Vec<u64> {
+    let mut values: Vec<u64> = Vec::new();
+    let (first, last) = date_range(now_ref, days);
+
+    for d in first.iter_days().take_while(|d| *d <= last) {
+        values.push(*data.get(&d).unwrap_or(&0));
+    }
+
+    values
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use githubstats::github::{GithubStats, GithubStatsOptions};
+use githubstats::GithubStatsError;
+
+mod common;
+use common::serve_http;
+
+// Client of a server which answers with the Accept header it received
+fn echo_client() -> GithubStats {
+    let base = serve_http(|request| {
+        let accept = request
+            .lines()
+            .find_map(|l| l.strip_prefix("accept: "))
+            .unwrap_or_default();
+
+        serde_json::json!({ "accept": accept }).to_string()
+    });
+
+    let options = GithubStatsOptions { rate_limit: Duration::ZERO, ..Default::default() };
+
+    let mut ghsc = GithubStats::new("key", options);
+    ghsc.set_api_base(&base);
+    ghsc.add_accept("application/vnd.github.preview+json");
+    ghsc
+}
+
+#[test]
+fn accept_is_replaced_for_one_request() {
+    let ghsc = echo_client();
+
+    let json = ghsc.get_json_with_accept("/repos/me/foo/stargazers", "application/vnd.github.star+json").unwrap();
+    assert_eq!(json, r#"{"accept":"application/vnd.github.star+json"}"#);
+}
+
+#[test]
+fn offline_request_with_accept_is_not_found() {
+    let mut ghsc = echo_client();
+    ghsc.set_offline(true);
+
+    assert!(matches!(
+        ghsc.get_json_with_accept("/repos/me/foo/stargazers", "application/vnd.github.star+json"),
+        Err(GithubStatsError::NotFound(_)),
+    ));
+}
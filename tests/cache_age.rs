@@ -0,0 +1,22 @@
+use std::fs;
+use std::time::Duration;
+
+use tempfile::TempDir;
+
+use githubstats::file_age;
+
+#[test]
+fn new_file_is_fresh() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("views.json");
+    fs::write(&path, "{}").unwrap();
+
+    assert!(file_age(&path).unwrap() < Duration::from_secs(1));
+}
+
+#[test]
+fn missing_file_is_error() {
+    let dir = TempDir::new().unwrap();
+
+    assert!(file_age(&dir.path().join("missing.json")).is_err());
+}
@@ -0,0 +1,54 @@
+use std::path::{Path, PathBuf};
+
+use githubstats::github::GithubStats;
+use githubstats::{parse_path_component, path_component};
+use githubstats::StatType::{Clones, Views};
+
+#[test]
+fn repo_named_like_stat_kind_does_not_collide() {
+    let a_views = GithubStats::stats_cache_file(Path::new("cache"), "owner", "a", Views);
+    let repo_a_views = GithubStats::stats_cache_file(Path::new("cache"), "owner", "a_views", Views);
+    let repo_a_views_clones = GithubStats::stats_cache_file(Path::new("cache"), "owner", "a_views", Clones);
+
+    assert_eq!(a_views, PathBuf::from("cache/repos/owner/a/views.json"));
+    assert_eq!(repo_a_views, PathBuf::from("cache/repos/owner/a_views/views.json"));
+    assert_ne!(a_views, repo_a_views);
+    assert_ne!(repo_a_views, repo_a_views_clones);
+}
+
+#[test]
+fn dotted_repo_name_is_kept() {
+    assert_eq!(
+        GithubStats::stats_cache_file(Path::new("cache"), "owner", "raspi.github.io", Clones),
+        PathBuf::from("cache/repos/owner/raspi.github.io/clones.json"),
+    );
+}
+
+#[test]
+fn unsafe_names_stay_inside_cache_dir() {
+    for name in ["..", ".", "a/b", "../../etc", ""] {
+        let p = GithubStats::stats_cache_file(Path::new("cache"), "owner", name, Views);
+
+        assert!(p.starts_with("cache/repos/owner"));
+        assert_eq!(p.components().count(), 5, "{} -> {}", name, p.display());
+        assert!(!p.components().any(|c| c.as_os_str() == ".."));
+    }
+}
+
+#[test]
+fn encoded_names_parse_back() {
+    for name in ["a", "raspi.github.io", "..", ".", "a/b", "../../etc", "", "ä %"] {
+        assert_eq!(parse_path_component(&path_component(name)).as_deref(), Some(name));
+    }
+
+    assert_eq!(parse_path_component("%2"), None);
+    assert_eq!(parse_path_component("%ZZ"), None);
+}
+
+#[test]
+fn cache_dir_is_configurable() {
+    assert_eq!(
+        GithubStats::stats_cache_file(Path::new("/var/cache/github-stats"), "owner", "a", Views),
+        PathBuf::from("/var/cache/github-stats/repos/owner/a/views.json"),
+    );
+}
@@ -0,0 +1,151 @@
+use githubstats::chart::{chart_from_stats, embedded_hash, ChartOptions, ChartType, OutputFormat, Theme};
+use githubstats::{RepoStats, Stats};
+use githubstats::StatType::{Clones, Views};
+
+mod common;
+use common::date;
+
+fn stats(views: u64) -> Vec<RepoStats> {
+    vec![
+        RepoStats {
+            date: date(2023, 3, 29),
+            views: Stats { count: views, uniques: 1 },
+            clones: Stats { count: 2, uniques: 1 },
+        },
+        RepoStats {
+            date: date(2023, 3, 30),
+            views: Stats { count: 5, uniques: 2 },
+            clones: Stats { count: 0, uniques: 0 },
+        },
+    ]
+}
+
+fn hash(stats: &[RepoStats], opts: &ChartOptions) -> String {
+    chart_from_stats(stats, "me", "foo", Views, date(2023, 3, 30), 7, opts).content_hash()
+}
+
+#[test]
+fn same_data_same_hash() {
+    let opts = ChartOptions::default();
+
+    assert_eq!(hash(&stats(3), &opts), hash(&stats(3), &opts));
+}
+
+#[test]
+fn changed_count_changes_hash() {
+    let opts = ChartOptions::default();
+
+    assert_ne!(hash(&stats(3), &opts), hash(&stats(4), &opts));
+}
+
+#[test]
+fn changed_options_change_hash() {
+    let opts = ChartOptions::default();
+    let wide = ChartOptions { width: 800, ..ChartOptions::default() };
+
+    assert_ne!(hash(&stats(3), &opts), hash(&stats(3), &wide));
+}
+
+#[test]
+fn other_traffic_type_changes_hash() {
+    let opts = ChartOptions::default();
+    let clones = chart_from_stats(&stats(3), "me", "foo", Clones, date(2023, 3, 30), 7, &opts);
+
+    assert_ne!(hash(&stats(3), &opts), clones.content_hash());
+}
+
+#[test]
+fn rendered_svg_embeds_hash() {
+    let opts = ChartOptions::default();
+    let mut chart = chart_from_stats(&stats(3), "me", "foo", Views, date(2023, 3, 30), 7, &opts);
+
+    let svg = chart.render_to_string().unwrap();
+
+    assert_eq!(embedded_hash(&svg), Some(chart.content_hash()));
+}
+
+#[test]
+fn bar_chart_has_own_hash() {
+    let opts = ChartOptions::default();
+    let bars = ChartOptions { chart_type: ChartType::Bars, ..ChartOptions::default() };
+
+    assert_ne!(hash(&stats(3), &opts), hash(&stats(3), &bars));
+
+    let mut chart = chart_from_stats(&stats(3), "me", "foo", Views, date(2023, 3, 30), 7, &bars);
+    let svg = chart.render_to_string().unwrap();
+
+    assert_eq!(embedded_hash(&svg), Some(chart.content_hash()));
+}
+
+#[test]
+fn png_has_own_hash() {
+    let opts = ChartOptions::default();
+    let png = ChartOptions { output_format: OutputFormat::Png, ..ChartOptions::default() };
+
+    assert_ne!(hash(&stats(3), &opts), hash(&stats(3), &png));
+    assert_eq!(png.output_format.extension(), "png");
+}
+
+#[test]
+fn dark_theme_has_own_hash() {
+    let opts = ChartOptions::default();
+    let dark = ChartOptions { theme: Theme::Dark, ..ChartOptions::default() };
+
+    assert_ne!(hash(&stats(3), &opts), hash(&stats(3), &dark));
+
+    let svg = chart_from_stats(&stats(3), "me", "foo", Views, date(2023, 3, 30), 7, &dark)
+        .render_to_string()
+        .unwrap();
+
+    assert!(svg.contains("#121212"));
+
+    // Axis labels use the configured font too
+    let serif = ChartOptions { font: Some(String::from("serif")), ..dark };
+    let svg = chart_from_stats(&stats(3), "me", "foo", Views, date(2023, 3, 30), 7, &serif)
+        .render_to_string()
+        .unwrap();
+
+    assert!(!svg.contains("sans-serif"));
+}
+
+#[test]
+fn annotation_changes_hash() {
+    let opts = ChartOptions::default();
+    let mut chart = chart_from_stats(&stats(3), "me", "foo", Views, date(2023, 3, 30), 7, &opts);
+    let before = chart.content_hash();
+
+    chart.add_vertical_annotation(date(2023, 3, 28), String::from("v1.0"));
+
+    assert_ne!(before, chart.content_hash());
+    assert!(chart.render_to_string().unwrap().contains("v1.0"));
+}
+
+#[test]
+fn line_chart_has_own_hash() {
+    let opts = ChartOptions::default();
+    let line = ChartOptions { chart_type: ChartType::Line, ..ChartOptions::default() };
+
+    assert_ne!(hash(&stats(3), &opts), hash(&stats(3), &line));
+
+    let svg = chart_from_stats(&stats(3), "me", "foo", Views, date(2023, 3, 30), 90, &line)
+        .render_to_string()
+        .unwrap();
+
+    assert!(svg.contains("<polyline"));
+}
+
+#[test]
+fn dates_run_left_to_right() {
+    let opts = ChartOptions::default();
+    let svg = chart_from_stats(&stats(3), "me", "foo", Views, date(2023, 3, 30), 7, &opts)
+        .render_to_string()
+        .unwrap();
+
+    assert!(svg.contains("Dates 2023-03-24 - 2023-03-30"));
+
+    // Axis labels are in date order and don't go past the last day
+    let first = svg.find(">\n2023-03-24\n<").unwrap();
+    let last = svg.find(">\n2023-03-30\n<").unwrap();
+    assert!(first < last);
+    assert!(!svg.contains("2023-03-31"));
+}
@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::Path;
+
+use tempfile::TempDir;
+
+use githubstats::clear_cache;
+
+fn seeded(dir: &Path) {
+    for repo in ["me/foo", "me/bar"] {
+        let d = dir.join("repos").join(repo);
+        fs::create_dir_all(&d).unwrap();
+        fs::write(d.join("views.json"), "{}").unwrap();
+        fs::write(d.join("views.json.etag"), "\"abc\"").unwrap();
+    }
+}
+
+#[test]
+fn clears_one_repo() {
+    let dir = TempDir::new().unwrap();
+    seeded(dir.path());
+
+    assert_eq!(clear_cache(dir.path(), Some("me/foo"), false).unwrap(), 2);
+    assert!(!dir.path().join("repos/me/foo").exists());
+    assert!(dir.path().join("repos/me/bar/views.json").exists());
+}
+
+#[test]
+fn dry_run_keeps_files() {
+    let dir = TempDir::new().unwrap();
+    seeded(dir.path());
+
+    assert_eq!(clear_cache(dir.path(), None, true).unwrap(), 4);
+    assert!(dir.path().join("repos/me/foo/views.json").exists());
+
+    assert_eq!(clear_cache(dir.path(), None, false).unwrap(), 4);
+    assert_eq!(clear_cache(dir.path(), None, false).unwrap(), 0);
+}
@@ -0,0 +1,63 @@
+// Fixtures shared by integration tests, not every test crate uses all of them
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::thread;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use tempfile::TempDir;
+
+use githubstats::db::Database;
+use githubstats::github::DayStats;
+
+pub fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(y, m, d).unwrap()
+}
+
+// GitHub API day with one unique visitor
+pub fn day(y: i32, m: u32, d: u32, count: u64) -> DayStats {
+    DayStats {
+        timestamp: Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap(),
+        count,
+        uniques: 1,
+    }
+}
+
+pub fn db_file(dir: &TempDir) -> PathBuf {
+    dir.path().join("t.sqlite")
+}
+
+// Empty database, removed when the returned directory is dropped
+pub fn temp_db() -> (TempDir, Database) {
+    let dir = TempDir::new().unwrap();
+    let db = Database::new(&db_file(&dir)).unwrap();
+    (dir, db)
+}
+
+// HTTP server answering 200 with the JSON returned by respond for each request,
+// given the request line and headers. Runs until the test ends, returns its base URL.
+pub fn serve_http(respond: impl Fn(&str) -> String + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => { break; }
+                    Ok(n) => { request.extend_from_slice(&buf[..n]) }
+                }
+            }
+
+            let body = respond(&String::from_utf8_lossy(&request));
+            let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+        }
+    });
+
+    base
+}
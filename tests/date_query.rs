@@ -0,0 +1,180 @@
+use chrono::NaiveDate;
+use tempfile::TempDir;
+
+use githubstats::db::Database;
+use githubstats::RepoTotals;
+use githubstats::StatType::Views;
+
+mod common;
+use common::{date, day, db_file, temp_db};
+
+// Single digit months and days on both sides of the range boundaries
+fn seeded() -> (TempDir, Database) {
+    let (dir, db) = temp_db();
+
+    db.update_traffic(Views, "me", "foo", vec![
+        day(2023, 1, 9, 1),
+        day(2023, 1, 10, 2),
+        day(2023, 9, 30, 3),
+        day(2023, 10, 1, 4),
+        day(2023, 10, 2, 5),
+    ]).unwrap();
+
+    (dir, db)
+}
+
+#[test]
+fn range_includes_single_digit_boundaries() {
+    let (_dir, db) = seeded();
+
+    // 2023-09-30 .. 2023-10-01
+    let stats = db.get_repo_stats("me", "foo", date(2023, 10, 1), 2).unwrap();
+    let dates: Vec<NaiveDate> = stats.iter().map(|s| s.date).collect();
+
+    assert_eq!(dates, vec![date(2023, 10, 1), date(2023, 9, 30)]);
+    assert_eq!(stats[0].views.count, 4);
+    assert_eq!(stats[1].views.count, 3);
+}
+
+#[test]
+fn range_within_single_digit_month() {
+    let (_dir, db) = seeded();
+
+    // 2023-01-09 .. 2023-01-10
+    let stats = db.get_repo_stats("me", "foo", date(2023, 1, 10), 2).unwrap();
+    let dates: Vec<NaiveDate> = stats.iter().map(|s| s.date).collect();
+
+    assert_eq!(dates, vec![date(2023, 1, 10), date(2023, 1, 9)]);
+}
+
+#[test]
+fn range_excludes_days_after_reference() {
+    let (_dir, db) = seeded();
+
+    let stats = db.get_repo_stats("me", "foo", date(2023, 9, 30), 1).unwrap();
+
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].date, date(2023, 9, 30));
+}
+
+#[test]
+fn stored_dates_are_in_date_order() {
+    let (_dir, db) = seeded();
+
+    assert_eq!(db.stored_dates("me", "foo").unwrap(), vec![
+        date(2023, 1, 9),
+        date(2023, 1, 10),
+        date(2023, 9, 30),
+        date(2023, 10, 1),
+        date(2023, 10, 2),
+    ]);
+}
+
+#[test]
+fn date_column_is_iso_8601() {
+    let (_dir, db) = seeded();
+
+    let (_, rows) = db.query("SELECT date FROM traffic WHERE m = 1 ORDER BY d").unwrap();
+
+    assert_eq!(rows, vec![vec!["2023-01-09"], vec!["2023-01-10"]]);
+}
+
+#[test]
+fn date_range_of_repo() {
+    let (_dir, db) = seeded();
+
+    assert_eq!(db.get_date_range("me", "foo").unwrap(), Some((date(2023, 1, 9), date(2023, 10, 2))));
+    assert_eq!(db.get_date_range("me", "missing").unwrap(), None);
+}
+
+#[test]
+fn totals_over_all_days() {
+    let (_dir, db) = seeded();
+
+    let totals = db.get_repo_totals("me", "foo").unwrap();
+
+    assert_eq!(totals.total_views, 15);
+    assert_eq!(totals.total_view_uniques, 5);
+    assert_eq!(totals.total_clones, 0);
+    assert_eq!(db.get_repo_totals("me", "missing").unwrap(), RepoTotals::default());
+}
+
+#[test]
+fn csv_export_of_date_range() {
+    let (_dir, db) = seeded();
+
+    let mut out: Vec<u8> = Vec::new();
+    let written = db.export_csv(&mut out, Some(date(2023, 9, 30)), Some(date(2023, 10, 1))).unwrap();
+
+    assert_eq!(written, 2);
+    assert_eq!(String::from_utf8(out).unwrap(), "\
+date,owner,repo,v_count,v_uniq,c_count,c_uniq
+2023-09-30,me,foo,3,1,0,0
+2023-10-01,me,foo,4,1,0,0
+");
+}
+
+#[test]
+fn csv_export_of_repo() {
+    let (_dir, db) = seeded();
+
+    let mut out: Vec<u8> = Vec::new();
+
+    assert_eq!(db.export_repo_csv("me", "foo", &mut out).unwrap(), 5);
+
+    let csv = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+
+    assert_eq!(lines[0], "date,v_count,v_uniq,c_count,c_uniq");
+    assert_eq!(lines[1], "2023-01-09,1,1,0,0");
+    assert_eq!(lines[5], "2023-10-02,5,1,0,0");
+}
+
+#[test]
+fn csv_import_round_trip() {
+    let (_dir, db) = seeded();
+
+    let mut out: Vec<u8> = Vec::new();
+    db.export_repo_csv("me", "foo", &mut out).unwrap();
+
+    assert_eq!(db.import_csv("me", "copy", &mut out.as_slice()).unwrap(), 5);
+    assert_eq!(db.stored_dates("me", "copy").unwrap(), db.stored_dates("me", "foo").unwrap());
+    assert_eq!(db.get_repo_totals("me", "copy").unwrap(), db.get_repo_totals("me", "foo").unwrap());
+
+    let bad = "date,v_count,v_uniq,c_count,c_uniq\n2023-01-01,1,x,0,0\n";
+    assert!(db.import_csv("me", "bad", &mut bad.as_bytes()).is_err());
+    assert!(db.stored_dates("me", "bad").unwrap().is_empty());
+}
+
+#[test]
+fn csv_import_skips_future_days_and_fails_as_a_whole() {
+    let (_dir, db) = seeded();
+
+    let future = "date,v_count,v_uniq,c_count,c_uniq\n2023-01-01,1,1,0,0\n9999-01-01,1,1,0,0\n";
+    assert_eq!(db.import_csv("me", "future", &mut future.as_bytes()).unwrap(), 1);
+
+    // Views fit, clones above i64::MAX can't be stored
+    let huge = "date,v_count,v_uniq,c_count,c_uniq\n2023-01-01,1,1,18446744073709551615,0\n";
+    assert!(db.import_csv("me", "huge", &mut huge.as_bytes()).is_err());
+    assert!(db.stored_dates("me", "huge").unwrap().is_empty());
+}
+
+#[test]
+fn cached_stats_follow_every_write() {
+    let (dir, db) = seeded();
+    let db = db.with_stats_cache();
+
+    assert_eq!(db.get_repo_stats("me", "foo", date(2023, 10, 2), 1).unwrap()[0].views.count, 5);
+
+    // Written through Deref, not a CachedDatabase method
+    db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 2, 6)]).unwrap();
+    assert_eq!(db.get_repo_stats("me", "foo", date(2023, 10, 2), 1).unwrap()[0].views.count, 6);
+
+    db.delete_repo("me", "foo").unwrap();
+    assert!(db.get_repo_stats("me", "foo", date(2023, 10, 2), 1).unwrap().is_empty());
+
+    // Another process writing the same file
+    let other = Database::new(&db_file(&dir)).unwrap();
+    other.update_traffic(Views, "me", "foo", vec![day(2023, 10, 2, 7)]).unwrap();
+    assert_eq!(db.get_repo_stats("me", "foo", date(2023, 10, 2), 1).unwrap()[0].views.count, 7);
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+
+use githubstats::date_range;
+use githubstats::sparkline::daily_values;
+
+mod common;
+use common::date;
+
+#[test]
+fn range_contains_given_number_of_days() {
+    let (first, last) = date_range(date(2023, 3, 30), 30);
+
+    assert_eq!(first, date(2023, 3, 1));
+    assert_eq!(last, date(2023, 3, 30));
+    assert_eq!((last - first).num_days() + 1, 30);
+}
+
+#[test]
+fn single_day_range() {
+    assert_eq!(date_range(date(2023, 3, 30), 1), (date(2023, 3, 30), date(2023, 3, 30)));
+}
+
+#[test]
+fn zero_days_still_contains_reference_day() {
+    assert_eq!(date_range(date(2023, 3, 30), 0), (date(2023, 3, 30), date(2023, 3, 30)));
+}
+
+#[test]
+fn range_crosses_month_and_year() {
+    let (first, last) = date_range(date(2024, 1, 2), 3);
+
+    assert_eq!(first, date(2023, 12, 31));
+    assert_eq!(last, date(2024, 1, 2));
+}
+
+#[test]
+fn range_over_leap_day() {
+    let (first, _) = date_range(date(2024, 3, 1), 2);
+
+    assert_eq!(first, date(2024, 2, 29));
+}
+
+#[test]
+fn sparkline_values_follow_range() {
+    let now_ref = date(2023, 3, 30);
+    let (first, last) = date_range(now_ref, 7);
+
+    let data: HashMap<NaiveDate, u64> = [
+        (first, 1),
+        (last, 2),
+        // Outside of range
+        (date(2023, 3, 23), 100),
+        (date(2023, 3, 31), 100),
+    ].iter().cloned().collect();
+
+    assert_eq!(daily_values(&data, now_ref, 7), vec![1, 0, 0, 0, 0, 0, 2]);
+}
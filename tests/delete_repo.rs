@@ -0,0 +1,82 @@
+use githubstats::StatType::{Clones, Views};
+
+mod common;
+use common::{date, day, temp_db};
+
+#[test]
+fn deletes_only_given_repo() {
+    let (_dir, db) = temp_db();
+
+    db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 1, 1), day(2023, 10, 2, 2)]).unwrap();
+    db.update_traffic(Clones, "me", "foo", vec![day(2023, 10, 2, 1)]).unwrap();
+    db.update_traffic(Views, "me", "bar", vec![day(2023, 10, 1, 3)]).unwrap();
+    db.update_stars("me", "foo", date(2023, 10, 2), 5).unwrap();
+
+    // 2 traffic rows and 1 star count
+    assert_eq!(db.delete_repo("me", "foo").unwrap(), 3);
+
+    assert!(!db.repo_exists("me", "foo").unwrap());
+    assert!(db.repo_exists("me", "bar").unwrap());
+
+    let (_, rows) = db.query("SELECT COUNT(*) FROM stars").unwrap();
+    assert_eq!(rows, vec![vec!["0"]]);
+
+    assert_eq!(db.delete_repo("me", "foo").unwrap(), 0);
+}
+
+#[test]
+fn delete_needs_exact_name() {
+    let (_dir, db) = temp_db();
+
+    db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 1, 1)]).unwrap();
+
+    for (owner, name) in [("me", "fo%"), ("me", "fo_"), ("%", "foo"), ("me", "FOO"), ("me", "fo")] {
+        assert_eq!(db.delete_repo(owner, name).unwrap(), 0);
+    }
+
+    assert!(db.repo_exists("me", "foo").unwrap());
+}
+
+#[test]
+fn prunes_days_before_date() {
+    let (_dir, db) = temp_db();
+
+    db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 1, 1), day(2023, 10, 2, 2), day(2023, 10, 3, 3)]).unwrap();
+    db.update_traffic(Views, "me", "bar", vec![day(2023, 10, 1, 1)]).unwrap();
+
+    let before = date(2023, 10, 3);
+
+    assert_eq!(db.prune_old_data(before, Some(("me", "foo")), true).unwrap(), 2);
+    assert_eq!(db.stored_dates("me", "foo").unwrap().len(), 3);
+
+    assert_eq!(db.prune_old_data(before, Some(("me", "foo")), false).unwrap(), 2);
+    assert_eq!(db.stored_dates("me", "foo").unwrap(), vec![before]);
+    assert!(db.repo_exists("me", "bar").unwrap());
+
+    assert_eq!(db.prune_old_data(before, None, false).unwrap(), 1);
+}
+
+#[test]
+fn rename_merges_overlapping_days() {
+    let (_dir, db) = temp_db();
+
+    db.update_traffic(Views, "me", "old", vec![day(2023, 10, 1, 1), day(2023, 10, 2, 2)]).unwrap();
+    db.update_traffic(Clones, "me", "old", vec![day(2023, 10, 2, 4)]).unwrap();
+    db.update_traffic(Views, "me", "new", vec![day(2023, 10, 2, 10), day(2023, 10, 3, 3)]).unwrap();
+    db.update_traffic(Views, "you", "old", vec![day(2023, 10, 1, 7)]).unwrap();
+
+    // Day 1 moved, day 2 merged
+    assert_eq!(db.rename_repo("me", "old", "new").unwrap(), 2);
+
+    assert!(!db.repo_exists("me", "old").unwrap());
+    assert!(db.repo_exists("you", "old").unwrap());
+
+    let (_, rows) = db.query("SELECT d, v_count, v_uniq, c_count FROM traffic WHERE owner='me' AND repo='new' ORDER BY d").unwrap();
+    assert_eq!(rows, vec![
+        vec!["1", "1", "1", "0"],
+        vec!["2", "12", "2", "4"],
+        vec!["3", "3", "1", "0"],
+    ]);
+
+    assert_eq!(db.rename_repo("me", "old", "new").unwrap(), 0);
+}
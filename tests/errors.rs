@@ -0,0 +1,80 @@
+use std::error::Error;
+
+use tempfile::TempDir;
+
+use githubstats::db::Database;
+use githubstats::github::{GithubStats, GithubStatsOptions};
+use githubstats::GithubStatsError;
+use githubstats::StatType::{Referrers, Views};
+
+mod common;
+use common::temp_db;
+
+#[test]
+fn directory_as_database_path_is_invalid_input() {
+    let dir = TempDir::new().unwrap();
+
+    assert!(matches!(
+        Database::validate_path(dir.path()),
+        Err(GithubStatsError::InvalidInput(_)),
+    ));
+}
+
+#[test]
+fn broken_json_is_parse_error() {
+    assert!(matches!(
+        GithubStats::parse_stats(Views, "{\"count\": 1"),
+        Err(GithubStatsError::ParseError(_)),
+    ));
+}
+
+#[test]
+fn referrers_are_not_daily_stats() {
+    assert!(matches!(
+        GithubStats::parse_stats(Referrers, "[]"),
+        Err(GithubStatsError::InvalidInput(_)),
+    ));
+}
+
+#[test]
+fn unsupported_proxy_is_invalid_input() {
+    assert!(matches!(
+        GithubStats::with_proxy("key", "ftp://127.0.0.1:21", GithubStatsOptions::default()),
+        Err(GithubStatsError::InvalidInput(_)),
+    ));
+}
+
+#[test]
+fn database_error_keeps_source() {
+    let (_dir, db) = temp_db();
+
+    let e: GithubStatsError = db.query("SELECT * FROM missing").unwrap_err().into();
+
+    assert!(matches!(e, GithubStatsError::DatabaseError(_)));
+    assert!(e.source().is_some());
+}
+
+#[test]
+fn rate_limited_shows_wait() {
+    let e = GithubStatsError::RateLimited { retry_after_secs: 60 };
+
+    assert_eq!(e.to_string(), "GitHub API rate limit exceeded, retry after 60s");
+    assert!(e.source().is_none());
+}
+
+#[test]
+fn unopenable_database_is_error() {
+    let dir = TempDir::new().unwrap();
+
+    assert!(Database::new(&dir.path().join("missing").join("t.sqlite")).is_err());
+}
+
+#[test]
+fn context_keeps_os_error_text() {
+    let e = std::io::Error::from_raw_os_error(28);
+    let text = e.to_string();
+    let e = githubstats::with_context(e, String::from("failed to write `x`"));
+
+    assert_eq!(e.to_string(), format!("failed to write `x`: {}", text));
+    assert!(text.contains("os error 28"));
+}
@@ -0,0 +1,17 @@
+use chrono::NaiveDate;
+
+use githubstats::{RepoStats, Stats};
+
+#[test]
+fn repo_stats_json_shape() {
+    let stats = RepoStats {
+        date: NaiveDate::from_ymd_opt(2023, 10, 1).unwrap(),
+        views: Stats { count: 5, uniques: 2 },
+        clones: Stats { count: 1, uniques: 1 },
+    };
+
+    assert_eq!(
+        serde_json::to_string(&stats).unwrap(),
+        r#"{"date":"2023-10-01","views":{"count":5,"uniques":2},"clones":{"count":1,"uniques":1}}"#,
+    );
+}
@@ -0,0 +1,53 @@
+use chrono::{Days, Duration, NaiveDate, Utc};
+
+use githubstats::github::DayStats;
+use githubstats::StatType::{Clones, Views};
+
+mod common;
+use common::temp_db;
+
+fn day_after_today(days: i64, count: u64) -> DayStats {
+    DayStats {
+        timestamp: Utc::now() + Duration::days(days),
+        count,
+        uniques: 1,
+    }
+}
+
+#[test]
+fn future_dated_days_are_skipped() {
+    let (_dir, db) = temp_db();
+
+    let changed = db.update_traffic(Views, "me", "foo", vec![
+        day_after_today(-1, 1),
+        day_after_today(0, 2),
+        day_after_today(2, 3),
+    ]).unwrap();
+
+    assert_eq!(changed, 2);
+
+    let today = Utc::now().date_naive();
+    let stored: Vec<NaiveDate> = db.stored_dates("me", "foo").unwrap();
+
+    assert_eq!(stored, vec![today.checked_sub_days(Days::new(1)).unwrap(), today]);
+    assert!(db.future_dates(today).unwrap().is_empty());
+}
+
+#[test]
+fn stored_future_dates_are_detected() {
+    let (_dir, db) = temp_db();
+
+    db.update_traffic(Clones, "me", "foo", vec![day_after_today(0, 1)]).unwrap();
+
+    // Stats from a clock that was ahead, seen from yesterday
+    let yesterday = Utc::now().date_naive().checked_sub_days(Days::new(1)).unwrap();
+    let future = db.future_dates(yesterday).unwrap();
+
+    assert_eq!(future.len(), 1);
+    assert_eq!(future[0].0.full_name(), "me/foo");
+    assert_eq!(future[0].1, Utc::now().date_naive());
+
+    // Charts end at the reference date
+    let stats = db.get_repo_stats("me", "foo", yesterday, 7).unwrap();
+    assert!(stats.is_empty());
+}
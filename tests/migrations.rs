@@ -0,0 +1,154 @@
+use std::path::{Path, PathBuf};
+
+use rusqlite::Connection;
+use tempfile::TempDir;
+
+use githubstats::db::{Database, SCHEMA_VERSION};
+
+// Schema of the first released version, before schema versioning
+const ORIGINAL_SCHEMA: &str = r#"
+  CREATE TABLE IF NOT EXISTS traffic (
+    y INTEGER NOT NULL,
+    m INTEGER NOT NULL,
+    d INTEGER NOT NULL,
+
+    owner TEXT NOT NULL,
+    repo TEXT NOT NULL,
+
+    c_count  INTEGER NOT NULL DEFAULT 0,
+    c_uniq   INTEGER NOT NULL DEFAULT 0,
+
+    v_count  INTEGER NOT NULL DEFAULT 0,
+    v_uniq   INTEGER NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (y, m, d, owner, repo)
+  )"#;
+
+fn create_original(path: &Path) {
+    let conn = Connection::open(path).unwrap();
+    conn.execute_batch(ORIGINAL_SCHEMA).unwrap();
+
+    conn.execute_batch(r#"
+      INSERT INTO traffic (y, m, d, owner, repo, c_count, c_uniq, v_count, v_uniq) VALUES
+        (2023, 3, 1, 'me', 'foo', 1, 1, 10, 5),
+        (2023, 3, 2, 'me', 'foo', 2, 1, 20, 6),
+        (2023, 3, 2, 'me', 'bar', 0, 0, 3, 3);
+    "#).unwrap();
+}
+
+// Everything describing the schema, as text rows
+fn schema(db: &Database) -> Vec<Vec<String>> {
+    let mut res: Vec<Vec<String>> = Vec::new();
+
+    let (_, version) = db.query("PRAGMA user_version").unwrap();
+    res.extend(version);
+
+    let (_, tables) = db.query(
+        "SELECT type, name FROM sqlite_master WHERE name NOT LIKE 'sqlite_%' ORDER BY type, name"
+    ).unwrap();
+
+    for table in &tables {
+        res.push(table.clone());
+
+        if table[0] != "table" {
+            continue;
+        }
+
+        let (_, columns) = db.query(&format!("PRAGMA table_info({})", table[1])).unwrap();
+        res.extend(columns);
+
+        let (_, indexes) = db.query(&format!("PRAGMA index_list({})", table[1])).unwrap();
+        for index in indexes {
+            let (_, index_columns) = db.query(&format!("PRAGMA index_info({})", index[1])).unwrap();
+            res.push(index);
+            res.extend(index_columns);
+        }
+    }
+
+    res
+}
+
+fn db_path(dir: &TempDir, name: &str) -> PathBuf {
+    dir.path().join(name)
+}
+
+#[test]
+fn migrated_schema_matches_new_database() {
+    let dir = TempDir::new().unwrap();
+
+    let old_path = db_path(&dir, "old.sqlite");
+    create_original(&old_path);
+
+    let migrated = Database::new(&old_path).unwrap();
+    let fresh = Database::new(&db_path(&dir, "new.sqlite")).unwrap();
+
+    assert_eq!(migrated.schema_version().unwrap(), SCHEMA_VERSION);
+    assert_eq!(fresh.schema_version().unwrap(), SCHEMA_VERSION);
+    assert_eq!(schema(&migrated), schema(&fresh));
+}
+
+#[test]
+fn migration_keeps_data() {
+    let dir = TempDir::new().unwrap();
+
+    let path = db_path(&dir, "old.sqlite");
+    create_original(&path);
+
+    let db = Database::new(&path).unwrap();
+
+    let (_, rows) = db.query(
+        "SELECT y, m, d, date, owner, repo, c_count, c_uniq, v_count, v_uniq FROM traffic ORDER BY owner, repo, y, m, d"
+    ).unwrap();
+
+    assert_eq!(rows, vec![
+        vec!["2023", "3", "2", "2023-03-02", "me", "bar", "0", "0", "3", "3"],
+        vec!["2023", "3", "1", "2023-03-01", "me", "foo", "1", "1", "10", "5"],
+        vec!["2023", "3", "2", "2023-03-02", "me", "foo", "2", "1", "20", "6"],
+    ]);
+}
+
+#[test]
+fn migration_runs_once() {
+    let dir = TempDir::new().unwrap();
+
+    let path = db_path(&dir, "old.sqlite");
+    create_original(&path);
+
+    let db = Database::new(&path).unwrap();
+    assert_eq!(db.migrate().unwrap(), 0);
+    drop(db);
+
+    // Reopening doesn't change anything either
+    let db = Database::new(&path).unwrap();
+    assert_eq!(db.migrate().unwrap(), 0);
+    assert_eq!(db.schema_version().unwrap(), SCHEMA_VERSION);
+}
+
+#[test]
+fn new_database_passes_integrity_check() {
+    let dir = TempDir::new().unwrap();
+    let db = Database::new(&db_path(&dir, "t.sqlite")).unwrap();
+
+    assert_eq!(db.check_integrity().unwrap(), vec!["ok"]);
+    assert_eq!(db.quick_check().unwrap(), vec!["ok"]);
+}
+
+#[test]
+fn garbage_database_can_be_recreated() {
+    let dir = TempDir::new().unwrap();
+    let path = db_path(&dir, "t.sqlite");
+
+    let garbage: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+    std::fs::write(&path, &garbage).unwrap();
+
+    assert!(Database::new(&path).is_err());
+
+    // Left behind by a crash, must not be applied to the new database
+    std::fs::write(db_path(&dir, "t.sqlite-wal"), b"stale").unwrap();
+
+    let (db, backup) = Database::recreate(&path).unwrap();
+
+    assert_eq!(db.schema_version().unwrap(), SCHEMA_VERSION);
+    assert_eq!(std::fs::read(&backup).unwrap(), garbage);
+    assert!(Database::sibling_files(&backup)[2].exists());
+}
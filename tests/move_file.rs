@@ -0,0 +1,20 @@
+use std::fs;
+
+use tempfile::TempDir;
+
+use githubstats::move_file;
+
+#[test]
+fn replaces_existing_target() {
+    let dir = TempDir::new().unwrap();
+    let from = dir.path().join(".tmp.abc.svg");
+    let to = dir.path().join("foo_views.svg");
+
+    fs::write(&from, "new").unwrap();
+    fs::write(&to, "old").unwrap();
+
+    move_file(&from, &to).unwrap();
+
+    assert_eq!(fs::read_to_string(&to).unwrap(), "new");
+    assert!(!from.exists());
+}
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use tempfile::TempDir;
+
+use githubstats::github::{GithubStats, GithubStatsOptions};
+use githubstats::StatType::{Clones, Views};
+
+mod common;
+use common::serve_http;
+
+// Answers every traffic endpoint of GitHub API, returns its base URL
+fn serve_traffic() -> String {
+    serve_http(|request| {
+        let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+        let body = if path.contains("/traffic/clones") {
+            r#"{"count": 3, "uniques": 2, "clones": [{"timestamp": "2023-10-01T00:00:00Z", "count": 3, "uniques": 2}]}"#
+        } else if path.contains("/traffic/views") {
+            r#"{"count": 5, "uniques": 1, "views": [{"timestamp": "2023-10-01T00:00:00Z", "count": 5, "uniques": 1}]}"#
+        } else if path.contains("/popular/referrers") {
+            r#"[{"referrer": "github.com", "count": 4, "uniques": 1}]"#
+        } else if path.contains("/popular/paths") {
+            r#"[{"path": "/me/foo", "title": "foo", "count": 5, "uniques": 1}]"#
+        } else {
+            "{}"
+        };
+
+        body.to_string()
+    })
+}
+
+fn client(cache: &TempDir, api_base: &str) -> GithubStats {
+    let options = GithubStatsOptions { rate_limit: Duration::ZERO, ..Default::default() };
+
+    let mut ghsc = GithubStats::new("key", options);
+    ghsc.set_cache_dir(cache.path());
+    ghsc.set_api_base(api_base);
+    ghsc
+}
+
+#[test]
+fn offline_fetch_reads_everything_prefetched() {
+    let cache = TempDir::new().unwrap();
+
+    client(&cache, &serve_traffic()).prefetch("me", "foo").unwrap();
+
+    // Nothing listens here, every answer must come from cache
+    let mut offline = client(&cache, "http://127.0.0.1:1");
+    offline.set_offline(true);
+
+    assert_eq!(offline.get_stats(Clones, "me", "foo").unwrap().days.len(), 1);
+    assert_eq!(offline.get_stats(Views, "me", "foo").unwrap().days.len(), 1);
+    assert_eq!(offline.get_referrers("me", "foo").unwrap()[0].referrer, "github.com");
+    assert_eq!(offline.get_popular_paths("me", "foo").unwrap()[0].path, "/me/foo");
+}
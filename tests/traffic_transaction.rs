@@ -0,0 +1,74 @@
+use chrono::Utc;
+
+use githubstats::db::Database;
+use githubstats::StatType::Views;
+
+mod common;
+use common::{day, db_file, temp_db};
+
+#[test]
+fn failed_write_rolls_back_batch() {
+    let (_dir, db) = temp_db();
+
+    // Counts above i64::MAX can't be stored, so the second day fails after the first was written
+    let res = db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 1, 1), day(2023, 10, 2, u64::MAX), day(2023, 10, 3, 3)]);
+
+    assert!(res.is_err());
+    assert!(db.stored_dates("me", "foo").unwrap().is_empty());
+}
+
+#[test]
+fn batch_is_committed() {
+    let (_dir, db) = temp_db();
+
+    assert_eq!(db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 1, 1), day(2023, 10, 2, 2)]).unwrap(), 2);
+    assert_eq!(db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 1, 1), day(2023, 10, 2, 3)]).unwrap(), 1);
+    assert_eq!(db.stored_dates("me", "foo").unwrap().len(), 2);
+}
+
+#[test]
+fn failed_repo_transaction_rolls_back_all_writes() {
+    let (_dir, db) = temp_db();
+
+    let res: Result<(), rusqlite::Error> = db.transaction(|| {
+        db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 1, 1)])?;
+        db.set_last_fetch("me", "foo", Utc::now())?;
+        db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 2, u64::MAX)])?;
+        Ok(())
+    });
+
+    assert!(res.is_err());
+    assert!(db.stored_dates("me", "foo").unwrap().is_empty());
+    assert!(db.last_fetch("me", "foo").unwrap().is_none());
+
+    db.transaction(|| {
+        db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 1, 1), day(2023, 10, 2, 2)])?;
+        db.set_last_fetch("me", "foo", Utc::now())
+    }).unwrap();
+
+    assert_eq!(db.stored_dates("me", "foo").unwrap().len(), 2);
+    assert!(db.last_fetch("me", "foo").unwrap().is_some());
+}
+
+#[test]
+fn reader_is_not_blocked_by_open_write() {
+    let (dir, db) = temp_db();
+    let path = db_file(&dir);
+    db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 1, 1)]).unwrap();
+
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    let mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+    assert_eq!(mode, "wal");
+
+    let reader = Database::open_read_only(&path).unwrap();
+
+    db.transaction(|| {
+        db.update_traffic(Views, "me", "foo", vec![day(2023, 10, 2, 2)])?;
+
+        // Uncommitted day isn't visible, but reading doesn't fail as locked
+        assert_eq!(reader.stored_dates("me", "foo")?.len(), 1);
+        Ok::<(), rusqlite::Error>(())
+    }).unwrap();
+
+    assert_eq!(reader.stored_dates("me", "foo").unwrap().len(), 2);
+}